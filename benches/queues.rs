@@ -0,0 +1,95 @@
+//! Compare the priority queues with a steady schedule, every dequeued item is rescheduled.
+//!
+//! Run with `cargo bench --bench queues`. On one x86_64 machine, with 16 pending items the heaps
+//! took about 70ns and the timer wheel about 190ns, with 256 the wheel was ahead at about 0.8µs
+//! vs 1.0µs, and with 4096 it was about 2.5x faster, 9.6µs vs 24µs. So the crossover is
+//! somewhere between a few dozen and a couple hundred pending items.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sched::pqueue::{
+    binaryheap::BinaryHeapQueue, staticheap::StaticTickHeap, wheel::TimerWheel,
+    TickPriorityDequeue, TickPriorityEnqueue,
+};
+
+const BLOCK: usize = 64;
+const BLOCKS: usize = 16;
+//reschedule up to about a second ahead at 48k
+const SPREAD: usize = 48_000;
+
+struct Lcg(usize);
+
+impl Lcg {
+    fn next(&mut self) -> usize {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+        self.0
+    }
+}
+
+fn fill<Q>(queue: &mut Q, pending: usize, rng: &mut Lcg)
+where
+    Q: TickPriorityEnqueue<usize>,
+{
+    for i in 0..pending {
+        assert!(queue.try_enqueue(rng.next() % SPREAD, i).is_ok());
+    }
+}
+
+//process BLOCKS blocks, starting at tick, rescheduling everything that is dequeued
+fn run<Q>(queue: &mut Q, tick: &mut usize, rng: &mut Lcg) -> usize
+where
+    Q: TickPriorityEnqueue<usize> + TickPriorityDequeue<usize>,
+{
+    let mut count = 0;
+    for _ in 0..BLOCKS {
+        let next = tick.wrapping_add(BLOCK);
+        while let Some((t, v)) = queue.dequeue_lt(next) {
+            let t = t.wrapping_add(1 + rng.next() % SPREAD);
+            assert!(queue.try_enqueue(t, v).is_ok());
+            count += 1;
+        }
+        *tick = next;
+    }
+    count
+}
+
+fn bench_queues(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reschedule");
+    for pending in [16usize, 256, 4096] {
+        group.bench_with_input(
+            BenchmarkId::new("BinaryHeapQueue", pending),
+            &pending,
+            |b, &pending| {
+                let mut rng = Lcg(1234);
+                let mut queue = BinaryHeapQueue::with_capacity(pending);
+                let mut tick = 0;
+                fill(&mut queue, pending, &mut rng);
+                b.iter(|| run(&mut queue, &mut tick, &mut rng));
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("StaticTickHeap", pending),
+            &pending,
+            |b, &pending| {
+                let mut rng = Lcg(1234);
+                let mut queue: Box<StaticTickHeap<usize, 4096>> = Box::default();
+                let mut tick = 0;
+                fill(&mut *queue, pending, &mut rng);
+                b.iter(|| run(&mut *queue, &mut tick, &mut rng));
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("TimerWheel", pending),
+            &pending,
+            |b, &pending| {
+                let mut rng = Lcg(1234);
+                let mut queue: Box<TimerWheel<usize, 4096>> = Box::default();
+                let mut tick = 0;
+                fill(&mut *queue, pending, &mut rng);
+                b.iter(|| run(&mut *queue, &mut tick, &mut rng));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_queues);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use crate::Float;
+use crate::{param::ParamGet, Float};
 
 const PERIOD_MICRO_MIN: Float = 0.001;
 const BPM_MIN: Float = 0.001;
@@ -15,6 +15,29 @@ pub trait Clock: Send {
     fn set_ppq(&mut self, ppq: usize);
 }
 
+/// A source for the period of each tick of a clock, see `RootClock`.
+///
+/// Any `ParamGet<Float>` is a source with a constant period, in micro seconds.
+pub trait ClockPeriodGet: Send + Sync {
+    /// The time, in micro seconds, from the clock's `tick` to the next.
+    ///
+    /// If the tempo changes during the tick, this is the period integrated over it, so the
+    /// periods add up to `tick_to_micros`.
+    fn period_micros_at(&self, tick: usize) -> Float;
+
+    /// `period_micros_at`, or `None` if the period can't be read without waiting, for instance
+    /// for a lock. `RootClock` keeps the last period it read then.
+    fn try_period_micros_at(&self, tick: usize) -> Option<Float> {
+        Some(self.period_micros_at(tick))
+    }
+
+    /// The time, in micro seconds, between tick 0 and `tick`.
+    fn tick_to_micros(&self, tick: usize) -> Float;
+
+    /// The tick, with a fraction, `micros` micro seconds after tick 0.
+    fn micros_to_tick(&self, micros: Float) -> Float;
+}
+
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClockData {
@@ -33,7 +56,7 @@ macro_rules! period_micro {
 #[macro_export]
 macro_rules! make_clock {
     ($bpm:expr, $ppq:expr) => {
-        crate::binding::bpm::ClockData {
+        $crate::clock::ClockData {
             bpm: $bpm,
             period_micros: period_micro!($bpm, $ppq),
             ppq: $ppq,
@@ -98,6 +121,23 @@ impl Clock for ClockData {
     }
 }
 
+impl<P> ClockPeriodGet for P
+where
+    P: ParamGet<Float>,
+{
+    fn period_micros_at(&self, _tick: usize) -> Float {
+        self.get()
+    }
+
+    fn tick_to_micros(&self, tick: usize) -> Float {
+        tick as Float * self.get()
+    }
+
+    fn micros_to_tick(&self, micros: Float) -> Float {
+        micros / self.get()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
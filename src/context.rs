@@ -1,20 +1,57 @@
 //! Context implementations
-use crate::{event::*, pqueue::TickPriorityEnqueue, tick::*, Float};
+use crate::{
+    event::*,
+    pqueue::{TickHandle, TickPriorityEnqueue},
+    tick::*,
+    Float,
+};
+use core::{
+    any::{Any, TypeId},
+    marker::PhantomData,
+};
+
+//the context ticks side of the ratio ChildContext reports, its resolution
+const RATIO_CONTEXT_TICKS: usize = 1_000_000;
 
 pub struct RootContext<'a, E> {
     tick: usize,
     ticks_per_second: usize,
     schedule: &'a mut dyn TickPriorityEnqueue<E>,
+    transport_offset: usize,
+    transport_epoch: usize,
+    transport_sync_tick: Option<usize>,
+    enqueue_failures: usize,
 }
 
 pub struct ChildContext<'a, E> {
     parent: &'a mut dyn EventEvalContext<E>,
     parent_tick_offset: isize,
+    tick_sub: Float,
     context_tick: usize,
+    context_tick_offset: isize,
     context_ticks_per_second: usize,
     context_tick_period_micros: Float,
 }
 
+/// A context that adds a scoped variable to its parent, for its children to read.
+///
+/// Everything else is forwarded to the parent. Variables are looked up from the innermost
+/// context out, so a value shadows values pushed with the same key further up the graph.
+pub struct VarContext<'a, E> {
+    parent: &'a mut dyn EventEvalContext<E>,
+    key: &'static str,
+    value: &'a dyn Any,
+}
+
+/// A typed key for a scoped context variable.
+///
+/// Values are found by `name` and `T`, a value pushed with the same name but a different type
+/// doesn't shadow values of this type further up the graph.
+pub struct ContextKey<T> {
+    name: &'static str,
+    _phantom: PhantomData<fn() -> T>,
+}
+
 impl<'a, E> RootContext<'a, E> {
     pub fn new(
         tick: usize,
@@ -25,22 +62,66 @@ impl<'a, E> RootContext<'a, E> {
             tick,
             ticks_per_second,
             schedule,
+            transport_offset: 0,
+            transport_epoch: 0,
+            transport_sync_tick: None,
+            enqueue_failures: 0,
         }
     }
 
     pub fn update_tick(&mut self, tick: usize) {
         self.tick = tick;
     }
+
+    /// The number of events that could not be scheduled through this context.
+    pub fn enqueue_failures(&self) -> usize {
+        self.enqueue_failures
+    }
+
+    /// Set the transport state, `offset` is subtracted from the tick to get the transport tick.
+    pub fn update_transport(&mut self, offset: usize, epoch: usize) {
+        self.transport_offset = offset;
+        self.transport_epoch = epoch;
+    }
+
+    /// Set the tick at which the transport may next be located, see
+    /// `TickContext::transport_sync_tick`.
+    pub fn update_transport_sync_tick(&mut self, tick: usize) {
+        self.transport_sync_tick = Some(tick);
+    }
 }
 
-impl<'a, E> EventSchedule<E> for RootContext<'a, E> {
-    fn event_try_schedule(&mut self, tick: TickSched, event: E) -> Result<(), E> {
+impl<'a, E> RootContext<'a, E> {
+    fn absolute(&self, tick: TickSched) -> usize {
         //in the root, context and absolute are the same
-        let tick = match tick {
+        match tick {
             TickSched::Absolute(t) | TickSched::ContextAbsolute(t) => t,
             TickSched::Relative(o) | TickSched::ContextRelative(o) => offset_tick(self.tick, o),
-        };
-        self.schedule.try_enqueue(tick, event)
+        }
+    }
+}
+
+impl<'a, E> EventSchedule<E> for RootContext<'a, E> {
+    fn event_try_schedule(&mut self, tick: TickSched, event: E) -> Result<(), E> {
+        let tick = self.absolute(tick);
+        let r = self.schedule.try_enqueue(tick, event);
+        if r.is_err() {
+            self.enqueue_failures += 1;
+        }
+        r
+    }
+
+    fn event_try_schedule_handle(
+        &mut self,
+        tick: TickSched,
+        event: E,
+    ) -> Result<Option<TickHandle>, E> {
+        let tick = self.absolute(tick);
+        let r = self.schedule.try_enqueue_handle(tick, event);
+        if r.is_err() {
+            self.enqueue_failures += 1;
+        }
+        r
     }
 }
 
@@ -51,9 +132,24 @@ impl<'a, E> TickContext for RootContext<'a, E> {
     fn ticks_per_second(&self) -> usize {
         self.ticks_per_second
     }
+    //context ticks are ticks, wrap instead of extrapolating
+    fn context_tick_offset(&self) -> isize {
+        0isize.wrapping_sub(self.tick as isize)
+    }
+    fn transport_tick(&self) -> usize {
+        self.tick.wrapping_sub(self.transport_offset)
+    }
+    fn transport_epoch(&self) -> usize {
+        self.transport_epoch
+    }
+    fn transport_sync_tick(&self) -> Option<usize> {
+        self.transport_sync_tick
+    }
 }
 
 impl<'a, E> ChildContext<'a, E> {
+    /// Create a child context, context tick 0 is assumed to be `context_tick` periods before now,
+    /// use `update_context_tick_offset` if it isn't.
     pub fn new(
         parent: &'a mut dyn EventEvalContext<E>,
         parent_tick_offset: isize,
@@ -62,29 +158,79 @@ impl<'a, E> ChildContext<'a, E> {
     ) -> Self {
         //XXX TEST
         let ps = 1.0e6 / context_tick_period_micros;
-        Self {
+        let tick_sub = parent.tick_sub();
+        let mut s = Self {
             parent,
             parent_tick_offset,
+            tick_sub,
             context_tick,
+            context_tick_offset: 0,
             context_ticks_per_second: ps as usize,
             context_tick_period_micros,
-        }
+        };
+        s.context_tick_offset = 0isize.wrapping_sub(context_to_ticks(to_offset(context_tick), &s));
+        s
     }
 
     pub fn update_parent_offset(&mut self, offset: isize) {
         self.parent_tick_offset = offset;
+        self.tick_sub = self.parent.tick_sub();
+    }
+
+    /// Set the offset from the parent's exact position, including its `tick_sub`, in fractional
+    /// ticks.
+    pub fn update_parent_offset_exact(&mut self, offset: Float) {
+        let exact = self.parent.tick_sub() + offset;
+        let ticks = num_traits::Float::floor(exact);
+        self.parent_tick_offset = ticks as isize;
+        self.tick_sub = exact - ticks;
+    }
+
+    /// The whole ticks from the parent's `tick_now` to this context's.
+    pub fn parent_offset(&self) -> isize {
+        self.parent_tick_offset
     }
 
     pub fn update_context_tick(&mut self, tick: usize) {
         self.context_tick = tick;
     }
+
+    /// Set the offset from this context's `tick_now` to the tick at which its context tick 0
+    /// happened.
+    pub fn update_context_tick_offset(&mut self, offset: isize) {
+        self.context_tick_offset = offset;
+    }
+
+    /// Translate a time in this context to a time in the parent context.
+    ///
+    /// Relative times are offset by `parent_tick_offset`. Context times are converted the same way
+    /// `TickSched::to_absolute` does, see `context_relative_to_offset` and
+    /// `context_absolute_to_offset`.
+    fn parent_sched(&self, tick: TickSched) -> TickSched {
+        let offset = match tick {
+            TickSched::Absolute(_) => return tick,
+            TickSched::Relative(offset) => offset,
+            TickSched::ContextAbsolute(ctick) => context_absolute_to_offset(ctick, self),
+            TickSched::ContextRelative(coffset) => context_relative_to_offset(coffset, self),
+        };
+        TickSched::Relative(offset.wrapping_add(self.parent_tick_offset))
+    }
 }
 
 impl<'a, E> EventSchedule<E> for ChildContext<'a, E> {
     fn event_try_schedule(&mut self, tick: TickSched, event: E) -> Result<(), E> {
-        //XXX TODO TRANSLATE TO CONTEXT TIME IF NEEDED
+        let tick = self.parent_sched(tick);
         self.parent.event_try_schedule(tick, event)
     }
+
+    fn event_try_schedule_handle(
+        &mut self,
+        tick: TickSched,
+        event: E,
+    ) -> Result<Option<TickHandle>, E> {
+        let tick = self.parent_sched(tick);
+        self.parent.event_try_schedule_handle(tick, event)
+    }
 }
 
 impl<'a, E> TickContext for ChildContext<'a, E> {
@@ -103,15 +249,150 @@ impl<'a, E> TickContext for ChildContext<'a, E> {
     fn tick_period_micros(&self) -> Float {
         self.parent.tick_period_micros()
     }
+    fn tick_sub(&self) -> Float {
+        self.tick_sub
+    }
     fn context_tick_period_micros(&self) -> Float {
         self.context_tick_period_micros
     }
+    fn context_tick_offset(&self) -> isize {
+        self.context_tick_offset
+    }
+    //the exact ratio of the periods, context_ticks_per_second is truncated
+    fn context_tick_ratio(&self) -> (usize, usize) {
+        //float to int casts saturate
+        let ticks =
+            num_traits::Float::round(context_to_ticks_exact(RATIO_CONTEXT_TICKS as Float, self))
+                as usize;
+        (RATIO_CONTEXT_TICKS, ticks)
+    }
+    fn transport_tick(&self) -> usize {
+        offset_tick(self.parent.transport_tick(), self.parent_tick_offset)
+    }
+    fn transport_epoch(&self) -> usize {
+        self.parent.transport_epoch()
+    }
+    fn transport_sync_tick(&self) -> Option<usize> {
+        self.parent.transport_sync_tick()
+    }
+    fn context_var(&self, key: &str, type_id: TypeId) -> Option<&dyn Any> {
+        self.parent.context_var(key, type_id)
+    }
 }
 
-/*
+impl<'a, E> VarContext<'a, E> {
+    pub fn new<T: 'static>(
+        parent: &'a mut dyn EventEvalContext<E>,
+        key: ContextKey<T>,
+        value: &'a T,
+    ) -> Self {
+        Self {
+            parent,
+            key: key.name,
+            value,
+        }
+    }
+}
+
+impl<'a, E> EventSchedule<E> for VarContext<'a, E> {
+    fn event_try_schedule(&mut self, tick: TickSched, event: E) -> Result<(), E> {
+        self.parent.event_try_schedule(tick, event)
+    }
+
+    fn event_try_schedule_handle(
+        &mut self,
+        tick: TickSched,
+        event: E,
+    ) -> Result<Option<TickHandle>, E> {
+        self.parent.event_try_schedule_handle(tick, event)
+    }
+}
+
+impl<'a, E> TickContext for VarContext<'a, E> {
+    fn tick_now(&self) -> usize {
+        self.parent.tick_now()
+    }
+    fn ticks_per_second(&self) -> usize {
+        self.parent.ticks_per_second()
+    }
+    fn tick_period_micros(&self) -> Float {
+        self.parent.tick_period_micros()
+    }
+    fn tick_sub(&self) -> Float {
+        self.parent.tick_sub()
+    }
+    fn context_tick_now(&self) -> usize {
+        self.parent.context_tick_now()
+    }
+    fn context_ticks_per_second(&self) -> usize {
+        self.parent.context_ticks_per_second()
+    }
+    fn context_tick_period_micros(&self) -> Float {
+        self.parent.context_tick_period_micros()
+    }
+    fn context_tick_offset(&self) -> isize {
+        self.parent.context_tick_offset()
+    }
+    fn context_tick_ratio(&self) -> (usize, usize) {
+        self.parent.context_tick_ratio()
+    }
+    fn transport_tick(&self) -> usize {
+        self.parent.transport_tick()
+    }
+    fn transport_epoch(&self) -> usize {
+        self.parent.transport_epoch()
+    }
+    fn transport_sync_tick(&self) -> Option<usize> {
+        self.parent.transport_sync_tick()
+    }
+    fn context_var(&self, key: &str, type_id: TypeId) -> Option<&dyn Any> {
+        if key == self.key && self.value.type_id() == type_id {
+            Some(self.value)
+        } else {
+            self.parent.context_var(key, type_id)
+        }
+    }
+}
+
+impl<T> ContextKey<T>
+where
+    T: Copy + 'static,
+{
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Read the innermost value for this key.
+    pub fn get<C>(&self, context: &C) -> Option<T>
+    where
+        C: TickContext + ?Sized,
+    {
+        context
+            .context_var(self.name, TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref::<T>())
+            .copied()
+    }
+}
+
+impl<T> Clone for ContextKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ContextKey<T> {}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::pqueue::{staticheap::StaticTickHeap, TickPriorityDequeue};
     pub struct TestContext {
         tick: usize,
         ticks_per_second: usize,
@@ -144,5 +425,280 @@ pub(crate) mod tests {
             self.ticks_per_second
         }
     }
+
+    #[test]
+    fn vars() {
+        static VEL: ContextKey<u8> = ContextKey::new("vel");
+        static VEL_WIDE: ContextKey<u16> = ContextKey::new("vel");
+        static INDEX: ContextKey<usize> = ContextKey::new("index");
+
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        let mut root = RootContext::new(10, 1000, &mut queue);
+        assert_eq!(None, VEL.get(&root));
+
+        let vel = 100u8;
+        let mut vcontext = VarContext::new(&mut root, VEL, &vel);
+        assert_eq!(Some(100), VEL.get(&vcontext));
+        //same name, different type
+        assert_eq!(None, VEL_WIDE.get(&vcontext));
+        assert_eq!(None, INDEX.get(&vcontext));
+
+        //doesn't shadow the other type
+        let wide = 300u16;
+        {
+            let mut wcontext = VarContext::new(&mut vcontext, VEL_WIDE, &wide);
+            let vel = 50u8;
+            let inner = VarContext::new(&mut wcontext, VEL, &vel);
+            assert_eq!(Some(50), VEL.get(&inner));
+            assert_eq!(Some(300), VEL_WIDE.get(&inner));
+        }
+
+        //seen through child contexts, scheduling is forwarded
+        let mut child = ChildContext::new(&mut vcontext, 2, 0, 1000.0);
+        let index = 3usize;
+        let mut icontext = VarContext::new(&mut child, INDEX, &index);
+        assert_eq!(Some(100), VEL.get(&icontext));
+        assert_eq!(Some(3), INDEX.get(&icontext));
+        assert_eq!(12, icontext.tick_now());
+        assert!(icontext
+            .event_try_schedule(TickSched::Relative(1), 0)
+            .is_ok());
+        assert_eq!(Some((13, 0)), queue.dequeue_lt(1000));
+    }
+
+    #[test]
+    fn child_exact() {
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        {
+            let mut root = RootContext::new(100, 1000, &mut queue);
+            assert_eq!(0.0, root.tick_sub());
+            //2.5 ticks per context tick
+            let mut child = ChildContext::new(&mut root, 0, 0, 2500.0);
+            child.update_parent_offset_exact(3.75);
+            assert_eq!(3, child.parent_offset());
+            assert_eq!(103, child.tick_now());
+            assert_eq!(0.75, child.tick_sub());
+            {
+                //fractions add up through the levels
+                let mut grandchild = ChildContext::new(&mut child, 0, 0, 2500.0);
+                assert_eq!(0.75, grandchild.tick_sub());
+                grandchild.update_parent_offset_exact(0.5);
+                assert_eq!(104, grandchild.tick_now());
+                assert_eq!(0.25, grandchild.tick_sub());
+                //104.25 + 2.5
+                assert!(grandchild
+                    .event_try_schedule(TickSched::ContextRelative(1), 0)
+                    .is_ok());
+                //104.25 + 5
+                assert!(grandchild
+                    .event_try_schedule(TickSched::ContextRelative(2), 1)
+                    .is_ok());
+                //whole ticks are relative to tick_now
+                assert!(grandchild
+                    .event_try_schedule(TickSched::Relative(1), 2)
+                    .is_ok());
+                grandchild.update_parent_offset(2);
+                assert_eq!(0.75, grandchild.tick_sub());
+            }
+            //103.75 + 0 and 103.75 - 2.5
+            assert!(child
+                .event_try_schedule(TickSched::ContextRelative(0), 3)
+                .is_ok());
+            assert!(child
+                .event_try_schedule(TickSched::ContextRelative(-1), 4)
+                .is_ok());
+            assert!(child.event_try_schedule(TickSched::Absolute(5), 5).is_ok());
+            assert!(child.event_try_schedule(TickSched::Relative(-3), 6).is_ok());
+            assert!(child.event_try_schedule(TickSched::Relative(0), 7).is_ok());
+        }
+        assert_eq!(
+            [
+                (5, 5),
+                (100, 6),
+                (101, 4),
+                (103, 7),
+                (104, 3),
+                (105, 2),
+                (107, 0),
+                (109, 1)
+            ],
+            drain(&mut queue)
+        );
+    }
+
+    fn drain(queue: &mut StaticTickHeap<usize, 16>) -> [(usize, usize); 8] {
+        let mut out = [(0, 0); 8];
+        for o in out.iter_mut() {
+            *o = queue.dequeue_lt(1000).unwrap();
+        }
+        assert_eq!(None, queue.dequeue_lt(1000));
+        out
+    }
+
+    #[test]
+    fn child_translate() {
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        {
+            //1000 ticks per second
+            let mut root = RootContext::new(100, 1000, &mut queue);
+            //starts 5 ticks after the root, 10 ticks per context tick
+            let mut child = ChildContext::new(&mut root, 5, 2, 10000.0);
+            {
+                //starts 3 ticks after the child, 2.5 ticks per context tick
+                let mut grandchild = ChildContext::new(&mut child, 3, 7, 2500.0);
+                assert_eq!(108, grandchild.tick_now());
+                assert!(grandchild
+                    .event_try_schedule(TickSched::Absolute(50), 0)
+                    .is_ok());
+                assert!(grandchild
+                    .event_try_schedule(TickSched::Relative(1), 1)
+                    .is_ok());
+                assert!(grandchild
+                    .event_try_schedule(TickSched::ContextRelative(4), 2)
+                    .is_ok());
+                assert!(grandchild
+                    .event_try_schedule(TickSched::ContextAbsolute(9), 3)
+                    .is_ok());
+                assert!(grandchild
+                    .event_try_schedule_handle(TickSched::ContextRelative(-2), 4)
+                    .is_ok());
+            }
+            assert!(child
+                .event_try_schedule(TickSched::ContextRelative(2), 5)
+                .is_ok());
+            assert!(child
+                .event_try_schedule(TickSched::ContextAbsolute(0), 6)
+                .is_ok());
+            assert!(child.event_try_schedule(TickSched::Relative(-5), 7).is_ok());
+        }
+        assert_eq!(
+            [
+                (50, 0),
+                (85, 6),
+                (100, 7),
+                (103, 4),
+                (109, 1),
+                (113, 3),
+                (118, 2),
+                (125, 5)
+            ],
+            drain(&mut queue)
+        );
+    }
+
+    #[test]
+    fn child_translate_offset_wraps() {
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        {
+            let mut root = RootContext::new(2, 1000, &mut queue);
+            let mut child = ChildContext::new(&mut root, -4, 0, 1000.0);
+            let mut grandchild = ChildContext::new(&mut child, -1, 0, 3000.0);
+            for (i, t) in [
+                TickSched::Relative(0),
+                TickSched::Relative(3),
+                TickSched::ContextRelative(1),
+                TickSched::ContextAbsolute(2),
+            ]
+            .iter()
+            .enumerate()
+            {
+                assert!(grandchild.event_try_schedule(*t, i).is_ok());
+            }
+            for (i, t) in [
+                TickSched::Relative(0),
+                TickSched::ContextRelative(4),
+                TickSched::ContextAbsolute(1),
+                TickSched::Absolute(0),
+            ]
+            .iter()
+            .enumerate()
+            {
+                assert!(child.event_try_schedule(*t, i + 4).is_ok());
+            }
+        }
+        assert_eq!(
+            [
+                (usize::MAX - 2, 0),
+                (usize::MAX - 1, 4),
+                (usize::MAX, 6),
+                (0, 1),
+                (0, 2),
+                (0, 7),
+                (2, 5),
+                (3, 3),
+            ],
+            drain(&mut queue)
+        );
+    }
+
+    #[test]
+    fn root_context_ticks_are_ticks() {
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        {
+            //not exact as a Float, and past isize::MAX
+            let mut root = RootContext::new(usize::MAX - 3, 48000, &mut queue);
+            assert_eq!(4, root.context_tick_offset());
+            assert_eq!(
+                usize::MAX - 3,
+                TickSched::ContextAbsolute(usize::MAX - 3).to_absolute(&root)
+            );
+            assert_eq!(1, TickSched::ContextAbsolute(1).to_absolute(&root));
+            assert_eq!(1, TickSched::ContextRelative(5).to_absolute(&root));
+            assert!(root
+                .event_try_schedule(TickSched::ContextAbsolute(usize::MAX), 0)
+                .is_ok());
+            assert!(root
+                .event_try_schedule(TickSched::ContextAbsolute(0), 1)
+                .is_ok());
+        }
+        assert_eq!(Some((usize::MAX, 0)), queue.dequeue_lt(1));
+        assert_eq!(Some((0, 1)), queue.dequeue_lt(1));
+    }
+
+    #[test]
+    fn child_translate_matches_to_absolute() {
+        let mut queue = StaticTickHeap::<usize, 16>::new();
+        let mut expected = [(0, 0); 8];
+        {
+            let mut root = RootContext::new(1000, 48000, &mut queue);
+            //97 bpm at 24 ppq, about 1237.11 ticks per context tick
+            let period = crate::clock::ClockData::period_micros(97.0, 24);
+            let mut child = ChildContext::new(&mut root, 0, 3, period);
+            //not the truncated context_ticks_per_second
+            assert_eq!((1_000_000, 1_237_113_402), child.context_tick_ratio());
+            for (i, t) in [
+                TickSched::ContextRelative(24),
+                TickSched::ContextRelative(1),
+                TickSched::ContextAbsolute(24),
+                TickSched::ContextAbsolute(5),
+            ]
+            .iter()
+            .enumerate()
+            {
+                expected[i] = (t.to_absolute(&child), i);
+                assert!(child.event_try_schedule(*t, i).is_ok());
+            }
+            //with a fractional position
+            child.update_parent_offset_exact(0.9);
+            for (i, t) in [
+                TickSched::ContextRelative(24),
+                TickSched::ContextRelative(1),
+                TickSched::ContextAbsolute(24),
+                TickSched::ContextAbsolute(5),
+            ]
+            .iter()
+            .enumerate()
+            {
+                expected[i + 4] = (t.to_absolute(&child), i + 4);
+                assert!(child.event_try_schedule(*t, i + 4).is_ok());
+            }
+        }
+        assert_eq!((30691, 0), expected[0]);
+        assert_eq!((30692, 4), expected[4]);
+        expected.sort_unstable();
+        for e in expected.iter() {
+            assert_eq!(Some(*e), queue.dequeue_lt(100_000));
+        }
+        assert_eq!(None, queue.dequeue_lt(100_000));
+    }
 }
-*/
@@ -0,0 +1,41 @@
+//! Disposal of events that are no longer scheduled.
+//!
+//! Dropping an event can free memory, which should not happen on a real-time thread. An
+//! `EventDispose` can move retired events somewhere else to be dropped, for instance into an spsc
+//! `Producer` whose `Consumer` is drained with `dispose_drain` on a non real-time thread.
+use crate::pqueue::spsc::{Consumer, Producer};
+
+pub trait EventDispose<E>: Send {
+    /// Try to take ownership of an event that is no longer scheduled, returns the event if it
+    /// cannot, in which case the caller drops it.
+    fn try_dispose(&mut self, event: E) -> Result<(), E>;
+}
+
+/// Drop events in place.
+impl<E> EventDispose<E> for () {
+    fn try_dispose(&mut self, event: E) -> Result<(), E> {
+        core::mem::drop(event);
+        Ok(())
+    }
+}
+
+impl<'a, E, const N: usize> EventDispose<E> for Producer<'a, E, N>
+where
+    E: Send,
+{
+    fn try_dispose(&mut self, event: E) -> Result<(), E> {
+        self.try_push(event)
+    }
+}
+
+/// Drop all the events in a disposal queue, returns the number of events dropped.
+///
+/// Call this from a non real-time thread.
+pub fn dispose_drain<E, const N: usize>(consumer: &mut Consumer<'_, E, N>) -> usize {
+    let mut count = 0;
+    while let Some(event) = consumer.pop() {
+        core::mem::drop(event);
+        count += 1;
+    }
+    count
+}
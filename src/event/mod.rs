@@ -1,11 +1,23 @@
 //! Events and event scheduling
-use crate::tick::*;
+use crate::{pqueue::TickHandle, tick::*};
 
+pub mod dispose;
 pub mod midi;
+pub mod pool;
 
 pub trait EventSchedule<E> {
     /// Try to schedule the event at the given tick.
     fn event_try_schedule(&mut self, tick: TickSched, event: E) -> Result<(), E>;
+
+    /// Try to schedule the event at the given tick, returning a handle that can cancel it, if the
+    /// underlying queue supports cancellation.
+    fn event_try_schedule_handle(
+        &mut self,
+        tick: TickSched,
+        event: E,
+    ) -> Result<Option<TickHandle>, E> {
+        self.event_try_schedule(tick, event).map(|_| None)
+    }
 }
 
 pub trait EventEvalContext<E>: EventSchedule<E> + TickContext {
@@ -23,13 +35,46 @@ pub mod boxed {
     use super::*;
     use core::cmp::Ordering;
 
+    /// A boxed event.
+    ///
+    /// Containers scheduled for the same tick are evaluated in `priority` order, lowest first.
+    /// Containers with the same priority are evaluated in the order they were scheduled.
+    ///
+    /// The `tag` is not used by the container, it can be used to find events to cancel.
     pub struct EventContainer {
         inner: alloc::boxed::Box<dyn EventEval<EventContainer>>,
+        priority: isize,
+        tag: usize,
     }
 
     impl EventContainer {
         pub fn new(event: alloc::boxed::Box<dyn EventEval<Self>>) -> Self {
-            Self { inner: event }
+            Self::with_priority(event, 0)
+        }
+
+        /// Create a container with the given priority, for instance, a note off could use a
+        /// lower priority than a note on so that it is evaluated first.
+        pub fn with_priority(
+            event: alloc::boxed::Box<dyn EventEval<Self>>,
+            priority: isize,
+        ) -> Self {
+            Self {
+                inner: event,
+                priority,
+                tag: 0,
+            }
+        }
+
+        pub fn priority(&self) -> isize {
+            self.priority
+        }
+
+        pub fn tag(&self) -> usize {
+            self.tag
+        }
+
+        pub fn set_tag(&mut self, tag: usize) {
+            self.tag = tag;
         }
     }
 
@@ -42,12 +87,9 @@ pub mod boxed {
         }
     }
 
-    //TODO drop, push to queue
     impl Ord for EventContainer {
         fn cmp(&self, other: &Self) -> Ordering {
-            let left: *const _ = self.inner.as_ref();
-            let right: *const _ = other.inner.as_ref();
-            left.cmp(&right)
+            self.priority.cmp(&other.priority)
         }
     }
 
@@ -58,8 +100,8 @@ pub mod boxed {
     }
 
     impl PartialEq for EventContainer {
-        fn eq(&self, _other: &Self) -> bool {
-            false //box, never equal
+        fn eq(&self, other: &Self) -> bool {
+            self.priority == other.priority
         }
     }
 
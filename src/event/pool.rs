@@ -0,0 +1,349 @@
+//! Pre-allocated events, so events can be created on a real-time thread.
+//!
+//! An `EventPool` holds up to `N` events of a single type, in place. Acquiring an event from a
+//! pool returns an `EventContainer`, which can be used like `boxed::EventContainer`, and events
+//! from pools of different types can share a schedule. When the container is dropped its slot is
+//! returned to the pool. Acquire and release are lock-free and never allocate, so containers can
+//! be dropped on any thread.
+//!
+//! Pools are `'static`, usually a `static`, so that containers don't need a lifetime.
+use super::*;
+use core::{
+    cell::UnsafeCell,
+    cmp::Ordering,
+    mem::MaybeUninit,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering as AOrdering},
+};
+
+//the free list head packs a tag, to avoid ABA, above the index.
+//on 32 bit targets both get 16 bits, so pools hold fewer than 65535 events and the tag wraps
+//after 65536 acquires and releases, a thread stalled inside `pop` for that long could still
+//see ABA
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const EMPTY: usize = INDEX_MASK;
+
+fn pack(tag: usize, index: usize) -> usize {
+    (tag << INDEX_BITS) | index
+}
+
+trait PoolRelease: Sync {
+    fn release(&self, index: usize);
+}
+
+/// A fixed size pool of events of type `T`.
+///
+/// On 32 bit targets `N` must be less than 65535.
+pub struct EventPool<T, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    next: [AtomicUsize; N],
+    //a stack of released slots
+    head: AtomicUsize,
+    //slots from here up have never been used
+    unused: AtomicUsize,
+}
+
+unsafe impl<T, const N: usize> Sync for EventPool<T, N> where T: Send {}
+
+/// An event acquired from an `EventPool`.
+///
+/// Like `boxed::EventContainer`, containers scheduled for the same tick are evaluated in
+/// `priority` order, lowest first, and the `tag` can be used to find events to cancel.
+pub struct EventContainer {
+    inner: NonNull<dyn EventEval<EventContainer>>,
+    pool: &'static dyn PoolRelease,
+    index: usize,
+    priority: isize,
+    tag: usize,
+}
+
+//the event is Send and the pool is Sync
+unsafe impl Send for EventContainer {}
+
+impl<T, const N: usize> EventPool<T, N>
+where
+    T: EventEval<EventContainer> + 'static,
+{
+    pub const fn new() -> Self {
+        assert!(N < EMPTY, "pool is too large");
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            next: [const { AtomicUsize::new(EMPTY) }; N],
+            head: AtomicUsize::new(EMPTY),
+            unused: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of events the pool can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Move `event` into the pool, returns it if the pool is full.
+    pub fn try_acquire(&'static self, event: T) -> Result<EventContainer, T> {
+        self.try_acquire_with_priority(event, 0)
+    }
+
+    /// Move `event` into the pool, with the given priority, returns it if the pool is full.
+    pub fn try_acquire_with_priority(
+        &'static self,
+        event: T,
+        priority: isize,
+    ) -> Result<EventContainer, T> {
+        match self.pop() {
+            None => Err(event),
+            Some(index) => {
+                let ptr = self.slots[index].get();
+                let inner = unsafe {
+                    (*ptr).write(event);
+                    NonNull::new_unchecked(ptr as *mut T as *mut dyn EventEval<EventContainer>)
+                };
+                Ok(EventContainer {
+                    inner,
+                    pool: self,
+                    index,
+                    priority,
+                    tag: 0,
+                })
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<usize> {
+        let mut head = self.head.load(AOrdering::Acquire);
+        loop {
+            let index = head & INDEX_MASK;
+            if index == EMPTY {
+                return self
+                    .unused
+                    .fetch_update(AOrdering::Relaxed, AOrdering::Relaxed, |u| {
+                        if u < N {
+                            Some(u + 1)
+                        } else {
+                            None
+                        }
+                    })
+                    .ok();
+            }
+            let next = self.next[index].load(AOrdering::Relaxed);
+            let tag = (head >> INDEX_BITS).wrapping_add(1);
+            match self.head.compare_exchange_weak(
+                head,
+                pack(tag, next),
+                AOrdering::Acquire,
+                AOrdering::Acquire,
+            ) {
+                Ok(_) => return Some(index),
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> PoolRelease for EventPool<T, N>
+where
+    T: Send,
+{
+    fn release(&self, index: usize) {
+        let mut head = self.head.load(AOrdering::Relaxed);
+        loop {
+            self.next[index].store(head & INDEX_MASK, AOrdering::Relaxed);
+            let tag = (head >> INDEX_BITS).wrapping_add(1);
+            match self.head.compare_exchange_weak(
+                head,
+                pack(tag, index),
+                AOrdering::Release,
+                AOrdering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Default for EventPool<T, N>
+where
+    T: EventEval<EventContainer> + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventContainer {
+    pub fn priority(&self) -> isize {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: isize) {
+        self.priority = priority;
+    }
+
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    pub fn set_tag(&mut self, tag: usize) {
+        self.tag = tag;
+    }
+}
+
+impl Drop for EventContainer {
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.inner.as_ptr());
+        }
+        self.pool.release(self.index);
+    }
+}
+
+impl EventEval<EventContainer> for EventContainer {
+    fn event_eval(&mut self, context: &mut dyn EventEvalContext<EventContainer>) -> TickResched {
+        unsafe { self.inner.as_mut() }.event_eval(context)
+    }
+}
+
+impl Ord for EventContainer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+impl PartialOrd for EventContainer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for EventContainer {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for EventContainer {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pqueue::{staticheap::StaticTickHeap, TickPriorityEnqueue},
+        sched::SchedExec,
+        spin::mutex::spin::SpinMutex,
+        tick::TickSched,
+    };
+    use core::sync::atomic::AtomicBool;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DropEvent(usize);
+
+    impl EventEval<EventContainer> for DropEvent {
+        fn event_eval(
+            &mut self,
+            _context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            TickResched::None
+        }
+    }
+
+    impl Drop for DropEvent {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, AOrdering::SeqCst);
+        }
+    }
+
+    static DROP_POOL: EventPool<DropEvent, 3> = EventPool::new();
+
+    #[test]
+    fn acquire_release() {
+        assert_eq!(3, DROP_POOL.capacity());
+        let a = DROP_POOL.try_acquire(DropEvent(0)).ok().unwrap();
+        let b = DROP_POOL
+            .try_acquire_with_priority(DropEvent(1), -1)
+            .ok()
+            .unwrap();
+        let c = DROP_POOL.try_acquire(DropEvent(2)).ok().unwrap();
+        match DROP_POOL.try_acquire(DropEvent(3)) {
+            Err(e) => assert_eq!(3, e.0),
+            Ok(_) => panic!("pool should be full"),
+        }
+        //the rejected event was handed back and dropped
+        assert_eq!(1, DROPS.load(AOrdering::SeqCst));
+        assert!(b < a);
+        assert!(a == c);
+
+        //release from another thread
+        #[cfg(feature = "std")]
+        std::thread::spawn(move || core::mem::drop(b))
+            .join()
+            .unwrap();
+        #[cfg(not(feature = "std"))]
+        core::mem::drop(b);
+        assert_eq!(2, DROPS.load(AOrdering::SeqCst));
+
+        let d = DROP_POOL.try_acquire(DropEvent(4)).ok().unwrap();
+        assert!(DROP_POOL.try_acquire(DropEvent(5)).is_err());
+        core::mem::drop((a, c, d));
+        assert_eq!(6, DROPS.load(AOrdering::SeqCst));
+
+        //all the slots are back
+        let all: [EventContainer; 3] =
+            core::array::from_fn(|i| DROP_POOL.try_acquire(DropEvent(i)).ok().unwrap());
+        core::mem::drop(all);
+        assert_eq!(9, DROPS.load(AOrdering::SeqCst));
+    }
+
+    static NOTE_CNT: AtomicUsize = AtomicUsize::new(0);
+    static SPAWNED: AtomicBool = AtomicBool::new(false);
+
+    struct NoteEvent;
+
+    impl EventEval<EventContainer> for NoteEvent {
+        fn event_eval(
+            &mut self,
+            _context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            NOTE_CNT.fetch_add(1, AOrdering::SeqCst);
+            TickResched::None
+        }
+    }
+
+    //creates notes while it is evaluated, like a graph would
+    struct SpawnEvent;
+
+    impl EventEval<EventContainer> for SpawnEvent {
+        fn event_eval(
+            &mut self,
+            context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            for i in 0..2 {
+                if let Ok(note) = NOTE_POOL.try_acquire(NoteEvent) {
+                    SPAWNED.store(true, AOrdering::SeqCst);
+                    let _ = context.event_try_schedule(TickSched::Relative(i), note);
+                }
+            }
+            TickResched::Relative(4)
+        }
+    }
+
+    static NOTE_POOL: EventPool<NoteEvent, 2> = EventPool::new();
+    static SPAWN_POOL: EventPool<SpawnEvent, 1> = EventPool::new();
+
+    #[test]
+    fn sched() {
+        //no std or alloc needed
+        let queue = SpinMutex::new(StaticTickHeap::<EventContainer, 8>::new());
+        let mut sched = SchedExec::new(&queue, &queue);
+        let spawn = SPAWN_POOL.try_acquire(SpawnEvent).ok().unwrap();
+        assert!(queue.lock().try_enqueue(0, spawn).is_ok());
+
+        //the notes are released after they're evaluated, so the pool never runs out
+        sched.run(16, 48000);
+        assert!(SPAWNED.load(AOrdering::SeqCst));
+        assert_eq!(8, NOTE_CNT.load(AOrdering::SeqCst));
+        assert!(SPAWN_POOL.try_acquire(SpawnEvent).is_err());
+    }
+}
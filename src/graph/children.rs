@@ -0,0 +1,278 @@
+//! `GraphChildExec` implementations for collections of `GraphNode`s.
+//!
+//! Leaves can be used as children by wrapping them in a `GraphLeafWrapper`.
+use crate::{
+    event::EventEvalContext,
+    graph::{ChildCount, GraphChildExec, GraphNode},
+};
+use core::ops::Range;
+
+fn slice_child_count<N>(children: &[N]) -> ChildCount {
+    if children.is_empty() {
+        ChildCount::None
+    } else {
+        ChildCount::Some(children.len())
+    }
+}
+
+fn slice_child_exec_range<N, E>(
+    children: &[N],
+    context: &mut dyn EventEvalContext<E>,
+    range: Range<usize>,
+) where
+    N: GraphNode<E>,
+{
+    let end = core::cmp::min(range.end, children.len());
+    if range.start < end {
+        for child in children[range.start..end].iter() {
+            child.node_exec(context);
+        }
+    }
+}
+
+impl<N, E> GraphChildExec<E> for [N]
+where
+    N: GraphNode<E>,
+{
+    fn child_count(&self) -> ChildCount {
+        slice_child_count(self)
+    }
+
+    fn child_exec_range(&self, context: &mut dyn EventEvalContext<E>, range: Range<usize>) {
+        slice_child_exec_range(self, context, range)
+    }
+}
+
+impl<N, E, const S: usize> GraphChildExec<E> for [N; S]
+where
+    N: GraphNode<E>,
+{
+    fn child_count(&self) -> ChildCount {
+        slice_child_count(self)
+    }
+
+    fn child_exec_range(&self, context: &mut dyn EventEvalContext<E>, range: Range<usize>) {
+        slice_child_exec_range(self, context, range)
+    }
+}
+
+macro_rules! impl_tuple_children {
+    ($count:expr; $($index:tt $t:ident),+) => {
+        impl<E, $($t),+> GraphChildExec<E> for ($($t,)+)
+        where
+            $($t: GraphNode<E>),+
+        {
+            fn child_count(&self) -> ChildCount {
+                ChildCount::Some($count)
+            }
+
+            fn child_exec_range(
+                &self,
+                context: &mut dyn EventEvalContext<E>,
+                range: Range<usize>,
+            ) {
+                $(
+                    if range.contains(&$index) {
+                        self.$index.node_exec(context);
+                    }
+                )+
+            }
+        }
+    };
+}
+
+impl_tuple_children!(1; 0 A);
+impl_tuple_children!(2; 0 A, 1 B);
+impl_tuple_children!(3; 0 A, 1 B, 2 C);
+impl_tuple_children!(4; 0 A, 1 B, 2 C, 3 D);
+impl_tuple_children!(5; 0 A, 1 B, 2 C, 3 D, 4 F);
+impl_tuple_children!(6; 0 A, 1 B, 2 C, 3 D, 4 F, 5 G);
+impl_tuple_children!(7; 0 A, 1 B, 2 C, 3 D, 4 F, 5 G, 6 H);
+impl_tuple_children!(8; 0 A, 1 B, 2 C, 3 D, 4 F, 5 G, 6 H, 7 I);
+
+#[cfg(feature = "with_alloc")]
+mod alloc_impls {
+    extern crate alloc;
+    use super::*;
+    use alloc::{boxed::Box, vec::Vec};
+
+    /// Allow boxed nodes, including `Box<dyn GraphNode<E>>`, to be used as children.
+    impl<N, E> GraphNode<E> for Box<N>
+    where
+        N: GraphNode<E> + ?Sized,
+    {
+        fn node_exec(&self, context: &mut dyn EventEvalContext<E>) {
+            self.as_ref().node_exec(context)
+        }
+    }
+
+    impl<N, E> GraphChildExec<E> for Box<[N]>
+    where
+        N: GraphNode<E>,
+    {
+        fn child_count(&self) -> ChildCount {
+            slice_child_count(self)
+        }
+
+        fn child_exec_range(&self, context: &mut dyn EventEvalContext<E>, range: Range<usize>) {
+            slice_child_exec_range(self, context, range)
+        }
+    }
+
+    impl<N, E> GraphChildExec<E> for Vec<N>
+    where
+        N: GraphNode<E>,
+    {
+        fn child_count(&self) -> ChildCount {
+            slice_child_count(self)
+        }
+
+        fn child_exec_range(&self, context: &mut dyn EventEvalContext<E>, range: Range<usize>) {
+            slice_child_exec_range(self, context, range)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::tests::TestContext,
+        graph::{func::LeafFunc, node::step_seq::StepSeq, GraphLeafWrapper, GraphNodeExec},
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTS: [AtomicUsize; 4] = [
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+    ];
+
+    type CountLeaf = GraphLeafWrapper<LeafFunc<fn(&mut dyn EventEvalContext<()>), ()>, ()>;
+
+    fn count0(_context: &mut dyn EventEvalContext<()>) {
+        COUNTS[0].fetch_add(1, Ordering::SeqCst);
+    }
+    fn count1(_context: &mut dyn EventEvalContext<()>) {
+        COUNTS[1].fetch_add(1, Ordering::SeqCst);
+    }
+    fn count2(_context: &mut dyn EventEvalContext<()>) {
+        COUNTS[2].fetch_add(1, Ordering::SeqCst);
+    }
+    fn count3(_context: &mut dyn EventEvalContext<()>) {
+        COUNTS[3].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn leaves() -> [CountLeaf; 4] {
+        [
+            GraphLeafWrapper::new(LeafFunc::new(count0 as fn(&mut dyn EventEvalContext<()>))),
+            GraphLeafWrapper::new(LeafFunc::new(count1 as fn(&mut dyn EventEvalContext<()>))),
+            GraphLeafWrapper::new(LeafFunc::new(count2 as fn(&mut dyn EventEvalContext<()>))),
+            GraphLeafWrapper::new(LeafFunc::new(count3 as fn(&mut dyn EventEvalContext<()>))),
+        ]
+    }
+
+    fn take_counts() -> [usize; 4] {
+        [
+            COUNTS[0].swap(0, Ordering::SeqCst),
+            COUNTS[1].swap(0, Ordering::SeqCst),
+            COUNTS[2].swap(0, Ordering::SeqCst),
+            COUNTS[3].swap(0, Ordering::SeqCst),
+        ]
+    }
+
+    //the counters are shared, so run everything that touches them in a single test
+    #[test]
+    fn children_exec() {
+        let mut context = TestContext::new(0, 44100);
+        take_counts();
+
+        //array
+        let children = leaves();
+        assert_eq!(
+            ChildCount::Some(4),
+            GraphChildExec::<()>::child_count(&children)
+        );
+        children.child_exec_all(&mut context);
+        assert_eq!([1, 1, 1, 1], take_counts());
+        children.child_exec(&mut context, 2);
+        assert_eq!([0, 0, 1, 0], take_counts());
+        children.child_exec(&mut context, 4);
+        assert_eq!([0, 0, 0, 0], take_counts());
+        children.child_exec_range(&mut context, 1..20);
+        assert_eq!([0, 1, 1, 1], take_counts());
+        children.child_exec_range(&mut context, 4..8);
+        assert_eq!([0, 0, 0, 0], take_counts());
+
+        //slice
+        let slice: &[CountLeaf] = &children[1..3];
+        assert_eq!(
+            ChildCount::Some(2),
+            GraphChildExec::<()>::child_count(slice)
+        );
+        slice.child_exec_all(&mut context);
+        assert_eq!([0, 1, 1, 0], take_counts());
+
+        //empty
+        let empty: [CountLeaf; 0] = [];
+        assert_eq!(ChildCount::None, GraphChildExec::<()>::child_count(&empty));
+        assert!(!GraphChildExec::<()>::child_any(&empty));
+        empty.child_exec_all(&mut context);
+        assert_eq!([0, 0, 0, 0], take_counts());
+
+        //tuple
+        let [a, b, c, _] = leaves();
+        let tuple = (c, a, b);
+        assert_eq!(
+            ChildCount::Some(3),
+            GraphChildExec::<()>::child_count(&tuple)
+        );
+        tuple.child_exec_all(&mut context);
+        assert_eq!([1, 1, 1, 0], take_counts());
+        tuple.child_exec(&mut context, 0);
+        assert_eq!([0, 0, 1, 0], take_counts());
+        tuple.child_exec_range(&mut context, 1..3);
+        assert_eq!([1, 1, 0, 0], take_counts());
+        tuple.child_exec(&mut context, 3);
+        assert_eq!([0, 0, 0, 0], take_counts());
+
+        //index into children with a step sequencer
+        context.set_tick(3);
+        let seq: StepSeq<usize, usize, true> = StepSeq::new(1, 3);
+        seq.graph_exec(&mut context, &children);
+        assert_eq!([0, 0, 0, 1], take_counts());
+        let seq: StepSeq<usize, usize, true> = StepSeq::new(1, 1);
+        seq.graph_exec(&mut context, &tuple);
+        assert_eq!([1, 0, 0, 0], take_counts());
+        let seq: StepSeq<usize, usize, false> = StepSeq::new(1, 1);
+        seq.graph_exec(&mut context, &tuple);
+        assert_eq!([1, 1, 1, 0], take_counts());
+
+        #[cfg(feature = "with_alloc")]
+        {
+            extern crate alloc;
+            use alloc::{boxed::Box, vec::Vec};
+
+            let children: Vec<Box<dyn GraphNode<()>>> = IntoIterator::into_iter(leaves())
+                .map(|l| Box::new(l) as Box<dyn GraphNode<()>>)
+                .collect();
+            assert_eq!(
+                ChildCount::Some(4),
+                GraphChildExec::<()>::child_count(&children)
+            );
+            children.child_exec_all(&mut context);
+            assert_eq!([1, 1, 1, 1], take_counts());
+            children.child_exec(&mut context, 1);
+            assert_eq!([0, 1, 0, 0], take_counts());
+
+            let children: Box<[CountLeaf]> = Box::new(leaves());
+            assert_eq!(
+                ChildCount::Some(4),
+                GraphChildExec::<()>::child_count(&children)
+            );
+            children.child_exec_range(&mut context, 2..4);
+            assert_eq!([0, 0, 1, 1], take_counts());
+        }
+    }
+}
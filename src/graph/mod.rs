@@ -1,5 +1,6 @@
 //! Graph items and evaluation
 
+mod children;
 pub mod func;
 pub mod leaf;
 pub mod node;
@@ -36,6 +36,8 @@ where
     T: num_traits::sign::Unsigned + NumCast + Send,
     E: Send,
 {
+    //usize::is_multiple_of needs rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
     fn graph_exec(&self, context: &mut dyn EventEvalContext<E>, children: &dyn GraphChildExec<E>) {
         let div: usize = NumCast::from(self.div.get()).expect("T should cast to usize");
 
@@ -45,12 +47,17 @@ where
             let period_micros =
                 (context.context_tick_period_micros() * div as Float) / mul as Float;
             let coffset = (mul * context.context_tick_now()) / div;
+            //tick 0 of the child and the parent happen together
+            let context_tick_offset = context.context_tick_offset();
             let mut ccontext = ChildContext::new(context, 0, coffset, period_micros);
             for i in 0..mul {
-                ccontext.update_parent_offset(
-                    ((i as Float * period_micros) / base_period_micros) as isize,
-                );
+                //offsets are from the parent's exact position, so they don't accumulate error
+                ccontext
+                    .update_parent_offset_exact((i as Float * period_micros) / base_period_micros);
                 ccontext.update_context_tick(coffset + i);
+                ccontext.update_context_tick_offset(
+                    context_tick_offset.wrapping_sub(ccontext.parent_offset()),
+                );
                 children.child_exec_all(&mut ccontext);
             }
         }
@@ -5,3 +5,4 @@ pub mod repeat;
 pub mod step_seq;
 pub mod tick_offset;
 pub mod tick_store;
+pub mod var_store;
@@ -31,6 +31,8 @@ where
     Index: ParamGet<usize>,
     E: Send,
 {
+    //usize::is_multiple_of needs rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
     fn graph_exec(&self, context: &mut dyn EventEvalContext<E>, children: &dyn GraphChildExec<E>) {
         let step_ticks = self.step_ticks.get();
 
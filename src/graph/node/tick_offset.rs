@@ -4,6 +4,7 @@ use crate::{
     graph::{GraphChildExec, GraphNodeExec},
     param::ParamGet,
     tick::offset_tick,
+    Float,
 };
 
 //only applies offset to context
@@ -27,9 +28,17 @@ where
 {
     fn graph_exec(&self, context: &mut dyn EventEvalContext<E>, children: &dyn GraphChildExec<E>) {
         let period_micros = context.context_tick_period_micros();
-        let context_tick = offset_tick(context.context_tick_now(), self.offset.get());
+        let offset = self.offset.get();
+        let context_tick = offset_tick(context.context_tick_now(), offset);
+        //tick 0 moves by the offset, in the other direction
+        let context_tick_offset = context
+            .context_tick_offset()
+            .wrapping_sub(num_traits::Float::round(
+                offset as Float * period_micros / context.tick_period_micros(),
+            ) as isize);
 
         let mut ccontext = ChildContext::new(context, 0, context_tick, period_micros);
+        ccontext.update_context_tick_offset(context_tick_offset);
         children.child_exec_all(&mut ccontext);
     }
 }
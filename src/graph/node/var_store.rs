@@ -0,0 +1,90 @@
+use crate::{
+    context::{ContextKey, VarContext},
+    event::EventEvalContext,
+    graph::{GraphChildExec, GraphNodeExec},
+    param::ParamGet,
+};
+
+///A graph node that pushes a scoped context variable and then calls its children.
+///
+///Unlike `ParamStore`, the value is only visible to this node's children, so the same subtree can
+///be used under several `VarStore`s with different values.
+pub struct VarStore<T, G> {
+    key: ContextKey<T>,
+    get: G,
+}
+
+impl<T, G> VarStore<T, G>
+where
+    T: Copy + Send + 'static,
+    G: ParamGet<T>,
+{
+    pub fn new(key: ContextKey<T>, get: G) -> Self {
+        Self { key, get }
+    }
+}
+
+impl<T, G, E> GraphNodeExec<E> for VarStore<T, G>
+where
+    T: Copy + Send + 'static,
+    G: ParamGet<T>,
+    E: Send,
+{
+    fn graph_exec(&self, context: &mut dyn EventEvalContext<E>, children: &dyn GraphChildExec<E>) {
+        let value = self.get.get();
+        let mut vcontext = VarContext::new(context, self.key, &value);
+        children.child_exec_all(&mut vcontext);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::tests::TestContext,
+        graph::{func::LeafFunc, GraphLeafWrapper, GraphNodeWrapper},
+    };
+    use core::sync::atomic::{AtomicIsize, Ordering};
+
+    static TRANSPOSE: ContextKey<i8> = ContextKey::new("transpose");
+    static CHAN: ContextKey<u8> = ContextKey::new("chan");
+    static NOTE: AtomicIsize = AtomicIsize::new(0);
+
+    fn note(context: &mut dyn EventEvalContext<()>) {
+        let transpose = TRANSPOSE.get(context).unwrap_or(0) as isize;
+        let chan = CHAN.get(context).map_or(-1, |c| c as isize);
+        NOTE.store(60 + transpose + chan * 1000, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn scoped() {
+        let mut context = TestContext::new(0, 44100);
+        //shared by both stores
+        let leaf = (GraphLeafWrapper::new(LeafFunc::new(
+            note as fn(&mut dyn EventEvalContext<()>),
+        )),);
+
+        leaf.child_exec_all(&mut context);
+        assert_eq!(-940, NOTE.load(Ordering::SeqCst));
+
+        let up = VarStore::new(TRANSPOSE, 12i8);
+        let down = VarStore::new(TRANSPOSE, -12i8);
+        up.graph_exec(&mut context, &leaf);
+        assert_eq!(-928, NOTE.load(Ordering::SeqCst));
+        down.graph_exec(&mut context, &leaf);
+        assert_eq!(-952, NOTE.load(Ordering::SeqCst));
+
+        //nested, the innermost value wins
+        let chan = VarStore::new(CHAN, 2u8);
+        let inner = (GraphNodeWrapper::new(VarStore::new(TRANSPOSE, 7i8), leaf),);
+        chan.graph_exec(&mut context, &inner);
+        assert_eq!(2067, NOTE.load(Ordering::SeqCst));
+        let outer = (GraphNodeWrapper::new(VarStore::new(TRANSPOSE, 1i8), inner),);
+        chan.graph_exec(&mut context, &outer);
+        assert_eq!(2067, NOTE.load(Ordering::SeqCst));
+
+        //the values are gone after the store returns
+        outer.child_exec_all(&mut context);
+        assert_eq!(-933, NOTE.load(Ordering::SeqCst));
+    }
+}
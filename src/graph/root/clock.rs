@@ -1,29 +1,51 @@
 use crate::{
+    clock::ClockPeriodGet,
     context::ChildContext,
     event::EventEvalContext,
     graph::{root::GraphRootExec, GraphChildExec},
     param::ParamGet,
-    tick::TickResched,
+    tick::{tick_diff, TickResched},
     Float,
 };
 
-#[cfg(not(feature = "std"))]
-use num_traits::float::FloatCore;
+/// The most times a `RootClock` runs its children in one evaluation.
+pub const MAX_RUNS_PER_EVAL: usize = 1024;
 
 /// A root of a graph tree that evaluates its children at an interval controlled by its
-/// period_micros `ParamGet`.
+/// period_micros `ClockPeriodGet`, a `ParamGet<Float>` for a constant period or a `TempoMap`.
+///
+/// The fraction of a context tick left over by each period is carried to the next, so a clock
+/// that follows a changing tempo stays exact. When a period is shorter than a context tick, the
+/// children run several times in one evaluation, at most `MAX_RUNS_PER_EVAL` times, shorter
+/// periods than that run the clock slower than requested.
+///
+/// If the period source can't be read without waiting, see `ClockPeriodGet::try_period_micros_at`,
+/// the last period is used.
+///
+/// When the context's transport epoch changes, the clock resyncs its `tick` to the transport
+/// position, waiting until the next whole period if the position falls between two. The clock
+/// wakes up at the context's `transport_sync_tick` between periods, so it resyncs at the tick the
+/// transport was located at.
+///
+/// Children's context tick 0 is extrapolated back from the current clock tick with the current
+/// period, so context absolute times around now stay exact when the period follows a tempo map.
+/// With a constant period it is the tick at which the clock's tick 0 happened, the transport's
+/// start after a resync.
 pub struct RootClock<P, R, RS, E> {
     pub(crate) tick: usize,
     pub(crate) tick_sub: Float,
     pub(crate) period_micros: P,
+    pub(crate) period_micros_last: Float,
     pub(crate) run: R,
     pub(crate) reset: RS,
+    pub(crate) transport_epoch: usize,
+    pub(crate) due: Option<usize>,
     pub(crate) _phantom: core::marker::PhantomData<E>,
 }
 
 impl<P, R, RS, E> RootClock<P, R, RS, E>
 where
-    P: ParamGet<Float>,
+    P: ClockPeriodGet,
     R: ParamGet<bool>,
     RS: ParamGet<bool>,
     R: ParamGet<bool>,
@@ -32,9 +54,12 @@ where
         Self {
             tick: 0,
             tick_sub: 0.0 as Float,
+            period_micros_last: period_micros.period_micros_at(0),
             period_micros,
             run,
             reset,
+            transport_epoch: 0,
+            due: None,
             _phantom: Default::default(),
         }
     }
@@ -42,7 +67,7 @@ where
 
 impl<P, R, RS, E> GraphRootExec<E> for RootClock<P, R, RS, E>
 where
-    P: ParamGet<Float>,
+    P: ClockPeriodGet,
     R: ParamGet<bool>,
     RS: ParamGet<bool>,
     E: Send,
@@ -53,30 +78,129 @@ where
         children: &mut dyn GraphChildExec<E>,
     ) -> TickResched {
         if self.run.get() {
-            let period_micros = self.period_micros.get();
-            let (tick, tick_sub) = if self.reset.get() {
+            let ctp = context.context_tick_period_micros();
+
+            let epoch = context.transport_epoch();
+            if epoch != self.transport_epoch {
+                self.transport_epoch = epoch;
+                if ctp > 0.0 {
+                    let position = context.transport_tick() as Float * ctp;
+                    let tick = num_traits::Float::ceil(self.period_micros.micros_to_tick(position));
+                    //a period of zero or less gives a tick that isn't finite or is negative
+                    if tick.is_finite() && tick >= 0.0 {
+                        let tick = tick as usize;
+                        let delay = (self.period_micros.tick_to_micros(tick) - position) / ctp;
+                        self.tick = tick;
+                        self.tick_sub = num_traits::Float::fract(delay);
+                        if delay >= 1.0 {
+                            return self.wait(context, num_traits::Float::floor(delay) as usize);
+                        }
+                    }
+                }
+            } else if let Some(due) = self.due {
+                //woken up to see if the transport was located, it wasn't
+                let delay = tick_diff(due, context.context_tick_now());
+                if delay > 0 {
+                    return self.wait(context, delay as usize);
+                }
+            }
+
+            let (mut tick, mut tick_sub) = if self.reset.get() {
                 (0, 0.0)
             } else {
                 (self.tick, self.tick_sub)
             };
-            let mut ccontext = ChildContext::new(context, 0, tick, period_micros);
-            children.child_exec_all(&mut ccontext);
+            //periods shorter than a context tick run the children several times, once for every
+            //clock tick that happens before the next context tick
+            for _ in 0..MAX_RUNS_PER_EVAL {
+                let period_micros = match self.period_micros.try_period_micros_at(tick) {
+                    Some(p) => {
+                        self.period_micros_last = p;
+                        p
+                    }
+                    None => self.period_micros_last,
+                };
+                let mut ccontext = ChildContext::new(context, 0, tick, period_micros);
+                //the exact time of this tick is a fraction of a tick after now
+                ccontext.update_parent_offset_exact(tick_sub);
+                children.child_exec_all(&mut ccontext);
 
-            let ctp = context.context_tick_period_micros();
-            if period_micros <= 0.0 || ctp <= 0.0 {
-                TickResched::ContextRelative(1)
-            } else {
-                let next = tick_sub + (period_micros / ctp);
-                self.tick_sub = next.fract();
-                self.tick = tick + 1;
-
-                //XXX what if next is less than 1?
-                //XXX could move root.node_exec in here execute multiple times..
-                assert!(next >= 1.0, "tick less than sample size not supported");
-                TickResched::ContextRelative(core::cmp::max(1, next.floor() as usize))
+                if period_micros > 0.0 && ctp > 0.0 {
+                    let next = tick_sub + (period_micros / ctp);
+                    tick += 1;
+                    if next >= 1.0 {
+                        self.tick_sub = num_traits::Float::fract(next);
+                        self.tick = tick;
+                        return self.wait(context, num_traits::Float::floor(next) as usize);
+                    }
+                    tick_sub = next;
+                } else {
+                    return self.wait(context, 1);
+                }
             }
+            //out of runs, the rest of this context tick's clock ticks start the next one
+            self.tick = tick;
+            self.tick_sub = 0.0;
+            self.wait(context, 1)
         } else {
+            self.due = None;
             TickResched::ContextRelative(1)
         }
     }
 }
+
+impl<P, R, RS, E> RootClock<P, R, RS, E> {
+    //wait delay context ticks for the next clock tick, or until the transport's sync tick if it
+    //comes first, in a root context ticks are context ticks
+    fn wait(&mut self, context: &dyn EventEvalContext<E>, delay: usize) -> TickResched {
+        self.due = Some(context.context_tick_now().wrapping_add(delay));
+        let delay = match context.transport_sync_tick() {
+            Some(sync) => match tick_diff(sync, context.tick_now()) {
+                d if d > 0 => core::cmp::min(delay, d as usize),
+                _ => delay,
+            },
+            None => delay,
+        };
+        TickResched::ContextRelative(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::tests::TestContext,
+        graph::{func::LeafFunc, GraphLeafWrapper},
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn tiny_period() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static TICK: AtomicUsize = AtomicUsize::new(0);
+
+        let leaf = GraphLeafWrapper::new(LeafFunc::new(|context: &mut dyn EventEvalContext<()>| {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            TICK.store(context.context_tick_now(), Ordering::SeqCst);
+        }));
+        let mut children = (leaf,);
+
+        //a billion clock ticks per tick, the runs are capped
+        let mut clock = RootClock::new(1.0e-6 as Float, true, false);
+        let mut context = TestContext::new(0, 1000);
+        assert_eq!(
+            TickResched::ContextRelative(1),
+            clock.event_eval(&mut context, &mut children)
+        );
+        assert_eq!(MAX_RUNS_PER_EVAL, RUNS.load(Ordering::SeqCst));
+
+        //and carry on from where they stopped
+        context.set_tick(1);
+        assert_eq!(
+            TickResched::ContextRelative(1),
+            clock.event_eval(&mut context, &mut children)
+        );
+        assert_eq!(2 * MAX_RUNS_PER_EVAL, RUNS.load(Ordering::SeqCst));
+        assert_eq!(2 * MAX_RUNS_PER_EVAL - 1, TICK.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,473 @@
+use crate::{
+    context::ChildContext,
+    event::EventEvalContext,
+    graph::{root::GraphRootExec, GraphChildExec},
+    param::ParamSet,
+    pqueue::TickPriorityDequeue,
+    tick::{offset_tick, tick_diff, TickResched},
+    Float,
+};
+use core::cmp::Ordering;
+
+/// MIDI clocks per quarter note.
+pub const MIDI_CLOCK_PPQ: usize = 24;
+
+const PHASE_GAIN: Float = 0.25;
+const PERIOD_GAIN: Float = 0.02;
+
+/// Incoming MIDI clock and transport messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MidiClockMessage {
+    /// 0xFC
+    Stop,
+    /// 0xF2, the position in 16th notes, 6 MIDI clocks each.
+    SongPosition(u16),
+    /// 0xFA, play from the start.
+    Start,
+    /// 0xFB, play from the current song position.
+    Continue,
+    /// 0xF8
+    Clock,
+}
+
+impl MidiClockMessage {
+    /// Parse a message from raw MIDI bytes, `None` if it isn't a clock or transport message.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0xF8, ..] => Some(Self::Clock),
+            [0xFA, ..] => Some(Self::Start),
+            [0xFB, ..] => Some(Self::Continue),
+            [0xFC, ..] => Some(Self::Stop),
+            [0xF2, lsb, msb, ..] if *lsb < 0x80 && *msb < 0x80 => {
+                Some(Self::SongPosition(*lsb as u16 | (*msb as u16) << 7))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A `MidiClockMessage` in the input queue of a `RootMidiClock`.
+///
+/// These all compare equal, so messages on the same tick are dequeued in the order they were
+/// enqueued.
+#[derive(Copy, Clone, Debug)]
+pub struct MidiClockInput(pub MidiClockMessage);
+
+impl From<MidiClockMessage> for MidiClockInput {
+    fn from(message: MidiClockMessage) -> Self {
+        Self(message)
+    }
+}
+
+impl Ord for MidiClockInput {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for MidiClockInput {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MidiClockInput {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for MidiClockInput {}
+
+/// A root of a graph tree that follows an external MIDI clock.
+///
+/// Messages are read from `input`, as `MidiClockInput`, timestamped with the tick they were
+/// received at, for instance from the `TickConsumer` of an spsc queue that a MIDI input thread
+/// writes to. The root is evaluated every tick so that it sees messages as they arrive.
+///
+/// The tempo is estimated with a phase locked loop that filters out jitter in the clock timing.
+/// Children run at `ppq` ticks per quarter note, spread evenly between MIDI clocks with the
+/// estimated period, but never ahead of the next MIDI clock, so they stop when the clock stops.
+/// Until the period is measured, after the first clock, the children run only on MIDI clocks.
+///
+/// The estimated tempo, in beats per minute, is written to `bpm`.
+pub struct RootMidiClock<I, B, E> {
+    pub(crate) input: I,
+    pub(crate) ppq: usize,
+    pub(crate) bpm: B,
+    pub(crate) phase_gain: Float,
+    pub(crate) period_gain: Float,
+    pub(crate) running: bool,
+    //the song position of the next MIDI clock, in MIDI clocks, u64 so the position math doesn't
+    //overflow on 32 bit targets
+    pub(crate) next_clock: u64,
+    //the next tick for the children
+    pub(crate) next_tick: u64,
+    //the filtered time of the last MIDI clock, valid when synced
+    pub(crate) clock_tick: usize,
+    pub(crate) clock_sub: Float,
+    pub(crate) synced: bool,
+    //the time the last MIDI clock was received
+    pub(crate) received: Option<usize>,
+    //estimated ticks per MIDI clock, 0 until measured
+    pub(crate) period: Float,
+    pub(crate) _phantom: core::marker::PhantomData<E>,
+}
+
+impl<I, E> RootMidiClock<I, (), E>
+where
+    I: TickPriorityDequeue<MidiClockInput>,
+{
+    pub fn new(input: I, ppq: usize) -> Self {
+        Self::with_bpm(input, ppq, ())
+    }
+}
+
+impl<I, B, E> RootMidiClock<I, B, E>
+where
+    I: TickPriorityDequeue<MidiClockInput>,
+    B: ParamSet<Float>,
+{
+    pub fn with_bpm(input: I, ppq: usize, bpm: B) -> Self {
+        Self::with_gains(input, ppq, bpm, PHASE_GAIN, PERIOD_GAIN)
+    }
+
+    /// Create with the gains of the phase locked loop.
+    ///
+    /// Each MIDI clock, the difference between the expected and actual time of the clock, times
+    /// `phase_gain`, is added to the expected time, and times `period_gain`, to the period.
+    /// Smaller gains filter more jitter but follow tempo changes more slowly.
+    pub fn with_gains(input: I, ppq: usize, bpm: B, phase_gain: Float, period_gain: Float) -> Self {
+        assert!(ppq > 0);
+        Self {
+            input,
+            ppq,
+            bpm,
+            phase_gain,
+            period_gain,
+            running: false,
+            next_clock: 0,
+            next_tick: 0,
+            clock_tick: 0,
+            clock_sub: 0.0,
+            synced: false,
+            received: None,
+            period: 0.0,
+            _phantom: Default::default(),
+        }
+    }
+
+    fn message(&mut self, tick: usize, message: MidiClockMessage) {
+        match message {
+            MidiClockMessage::Stop => self.running = false,
+            MidiClockMessage::SongPosition(p) => {
+                self.next_clock = p as u64 * 6;
+                //the first tick at or after the position
+                self.next_tick =
+                    (self.next_clock * self.ppq as u64).div_ceil(MIDI_CLOCK_PPQ as u64);
+            }
+            MidiClockMessage::Start => {
+                self.next_clock = 0;
+                self.next_tick = 0;
+                self.running = true;
+                self.synced = false;
+            }
+            MidiClockMessage::Continue => {
+                self.running = true;
+                self.synced = false;
+            }
+            MidiClockMessage::Clock => {
+                self.clock(tick);
+                //clocks keep the tempo while stopped but don't move the position
+                if self.running {
+                    self.next_clock += 1;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, tick: usize) {
+        let received = self.received.replace(tick);
+        if self.synced && self.period > 0.0 {
+            let expected = self.clock_sub + self.period;
+            let err = tick_diff(tick, self.clock_tick) as Float - expected;
+            if err.abs() < self.period / 2.0 {
+                self.period += self.period_gain * err;
+                self.set_clock_time(expected + self.phase_gain * err);
+                return;
+            }
+        }
+        //not locked, sync to this clock and measure the period from the last one
+        if self.synced || self.period <= 0.0 {
+            if let Some(r) = received {
+                let interval = tick_diff(tick, r);
+                if interval > 0 {
+                    self.period = interval as Float;
+                }
+            }
+        }
+        self.clock_tick = tick;
+        self.clock_sub = 0.0;
+        self.synced = true;
+    }
+
+    //set the time of the last clock, relative to the current `clock_tick`
+    fn set_clock_time(&mut self, time: Float) {
+        let whole = num_traits::Float::floor(time);
+        self.clock_tick = offset_tick(self.clock_tick, whole as isize);
+        self.clock_sub = time - whole;
+    }
+}
+
+impl<I, B, E> GraphRootExec<E> for RootMidiClock<I, B, E>
+where
+    I: TickPriorityDequeue<MidiClockInput>,
+    B: ParamSet<Float>,
+    E: Send,
+{
+    fn event_eval(
+        &mut self,
+        context: &mut dyn EventEvalContext<E>,
+        children: &mut dyn GraphChildExec<E>,
+    ) -> TickResched {
+        let now = context.tick_now();
+        while let Some((tick, MidiClockInput(message))) = self.input.dequeue_lt(now.wrapping_add(1))
+        {
+            self.message(tick, message);
+        }
+        if self.period > 0.0 {
+            self.bpm.set(
+                60.0e6 / (self.period * context.tick_period_micros() * MIDI_CLOCK_PPQ as Float),
+            );
+        }
+
+        if self.running && self.synced {
+            let ratio = self.ppq as Float / MIDI_CLOCK_PPQ as Float;
+            let period_micros = self.period * context.tick_period_micros() / ratio;
+            //run every tick before the next MIDI clock that is due, late ticks run now
+            while self.next_tick * (MIDI_CLOCK_PPQ as u64) < self.next_clock * self.ppq as u64 {
+                //the position of the tick after the last MIDI clock, in MIDI clocks
+                let clocks = self.next_tick as Float / ratio - (self.next_clock - 1) as Float;
+                if clocks > 0.0 && self.period <= 0.0 {
+                    break;
+                }
+                let time = self.clock_sub
+                    + clocks * self.period
+                    + tick_diff(self.clock_tick, now) as Float;
+                if time >= 1.0 {
+                    break;
+                }
+                //context ticks wrap like ticks
+                let mut ccontext =
+                    ChildContext::new(context, 0, self.next_tick as usize, period_micros);
+                ccontext.update_parent_offset_exact(if time > 0.0 { time } else { 0.0 });
+                children.child_exec_all(&mut ccontext);
+                self.next_tick += 1;
+            }
+        }
+        TickResched::ContextRelative(1)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        context::tests::TestContext,
+        graph::{func::LeafFunc, GraphLeafWrapper},
+        pqueue::{binaryheap::BinaryHeapQueue, TickPriorityEnqueue},
+        spin::mutex::spin::SpinMutex,
+    };
+
+    type Log = SpinMutex<Vec<(Float, usize)>>;
+
+    //record the exact time and context tick of every run
+    fn record(log: &'static Log) -> impl GraphChildExec<()> {
+        (GraphLeafWrapper::new(LeafFunc::new(
+            move |context: &mut dyn EventEvalContext<()>| {
+                log.lock().push((
+                    context.tick_now() as Float + context.tick_sub(),
+                    context.context_tick_now(),
+                ));
+            },
+        )),)
+    }
+
+    fn run<I, B>(
+        root: &mut RootMidiClock<I, B, ()>,
+        children: &mut dyn GraphChildExec<()>,
+        ticks: usize,
+    ) where
+        I: TickPriorityDequeue<MidiClockInput>,
+        B: ParamSet<Float>,
+    {
+        let mut context = TestContext::new(0, 48000);
+        for tick in 0..ticks {
+            context.set_tick(tick);
+            assert_eq!(
+                TickResched::ContextRelative(1),
+                root.event_eval(&mut context, children)
+            );
+        }
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            Some(MidiClockMessage::Clock),
+            MidiClockMessage::from_bytes(&[0xF8])
+        );
+        assert_eq!(
+            Some(MidiClockMessage::Start),
+            MidiClockMessage::from_bytes(&[0xFA])
+        );
+        assert_eq!(
+            Some(MidiClockMessage::Continue),
+            MidiClockMessage::from_bytes(&[0xFB])
+        );
+        assert_eq!(
+            Some(MidiClockMessage::Stop),
+            MidiClockMessage::from_bytes(&[0xFC])
+        );
+        assert_eq!(
+            Some(MidiClockMessage::SongPosition(0x3FFF)),
+            MidiClockMessage::from_bytes(&[0xF2, 0x7F, 0x7F])
+        );
+        assert_eq!(
+            Some(MidiClockMessage::SongPosition(130)),
+            MidiClockMessage::from_bytes(&[0xF2, 2, 1])
+        );
+        assert_eq!(None, MidiClockMessage::from_bytes(&[0xF2, 2]));
+        assert_eq!(None, MidiClockMessage::from_bytes(&[0x90, 60, 127]));
+    }
+
+    #[test]
+    fn arrival_order() {
+        static LOG: Log = SpinMutex::new(Vec::new());
+
+        //the clock comes before the start, so the first tick is on the next clock
+        let mut input = BinaryHeapQueue::<MidiClockInput>::with_capacity(8);
+        assert!(input
+            .try_enqueue(10, MidiClockMessage::Clock.into())
+            .is_ok());
+        assert!(input
+            .try_enqueue(10, MidiClockMessage::Start.into())
+            .is_ok());
+        assert!(input
+            .try_enqueue(1010, MidiClockMessage::Clock.into())
+            .is_ok());
+
+        let mut root = RootMidiClock::new(input, 24);
+        let mut children = record(&LOG);
+        run(&mut root, &mut children, 2000);
+        assert_eq!(vec![(1010.0, 0)], *LOG.lock());
+    }
+
+    #[test]
+    fn song_position_overflow() {
+        let mut root = RootMidiClock::<_, (), ()>::new(
+            BinaryHeapQueue::<MidiClockInput>::with_capacity(8),
+            960,
+        );
+        //would overflow a 32 bit usize
+        root.message(0, MidiClockMessage::SongPosition(0x3FFF));
+        assert_eq!(0x3FFF * 6, root.next_clock);
+        assert_eq!(0x3FFF * 6 * 40, root.next_tick);
+        root.next_clock = 1 << 40;
+        root.message(0, MidiClockMessage::Start);
+        assert_eq!(0, root.next_tick);
+    }
+
+    #[test]
+    fn follow() {
+        static LOG: Log = SpinMutex::new(Vec::new());
+        static BPM: SpinMutex<Float> = SpinMutex::new(0.0);
+
+        //120 bpm at 48k is a MIDI clock every 1000 ticks, with jitter
+        let jitter = [0isize, 7, -5, 3, -8, 6, -2, 8, -7, 1, 4, -4];
+        let mut input = BinaryHeapQueue::<MidiClockInput>::with_capacity(128);
+        assert!(input
+            .try_enqueue(100, MidiClockMessage::Start.into())
+            .is_ok());
+        for k in 0..96 {
+            let tick = 100 + k * 1000;
+            let tick = offset_tick(tick, jitter[k % jitter.len()]);
+            assert!(input
+                .try_enqueue(tick, MidiClockMessage::Clock.into())
+                .is_ok());
+        }
+
+        //4 ticks per MIDI clock, 250 ticks apart
+        let mut root = RootMidiClock::with_bpm(input, 96, &BPM);
+        let mut children = record(&LOG);
+        run(&mut root, &mut children, 100_000);
+
+        let log = LOG.lock();
+        //stops at the last MIDI clock
+        assert_eq!(96 * 4, log.len());
+        for (i, (time, ctick)) in log.iter().enumerate() {
+            assert_eq!(i, *ctick);
+            //the first ticks wait for the period to be measured, then the loop settles
+            let err = (time - (100.0 + i as Float * 250.0)).abs();
+            if (4..96).contains(&i) {
+                assert!(err < 24.0);
+            } else if i >= 96 {
+                //a late clock delays the tick on it, the jitter is filtered from the others
+                assert!(err <= 8.0);
+                if i >= 192 && i % 4 != 0 {
+                    assert!(err < 2.5);
+                }
+            }
+        }
+        for w in log.windows(2) {
+            assert!(w[1].0 >= w[0].0);
+        }
+        assert!((120.0 - *BPM.lock()).abs() < 0.5);
+    }
+
+    #[test]
+    fn transport() {
+        static LOG: Log = SpinMutex::new(Vec::new());
+
+        let mut input = BinaryHeapQueue::<MidiClockInput>::with_capacity(128);
+        let mut at = |tick: usize, message: MidiClockMessage| {
+            assert!(input.try_enqueue(tick, message.into()).is_ok())
+        };
+        at(0, MidiClockMessage::Start);
+        //stop after 8 clocks, 2 beats at 96 ppq, and continue from the second bar, with the
+        //continue before the clock on the same tick
+        at(7900, MidiClockMessage::Stop);
+        at(20000, MidiClockMessage::SongPosition(16));
+        at(30000, MidiClockMessage::Continue);
+        for k in 0..40 {
+            at(k * 1000, MidiClockMessage::Clock);
+        }
+
+        let mut root = RootMidiClock::new(input, 96);
+        let mut children = record(&LOG);
+        run(&mut root, &mut children, 40_000);
+
+        let log = LOG.lock();
+        let ticks: Vec<usize> = log.iter().map(|(_, t)| *t).collect();
+        let expected: Vec<usize> = (0..32).chain(384..424).collect();
+        assert_eq!(expected, ticks);
+        //the clock keeps the tempo while stopped, the first tick after continue is on the clock
+        assert_eq!(30000.0, log[32].0);
+        assert_eq!(30250.0, log[33].0);
+
+        //start over
+        let mut input = BinaryHeapQueue::<MidiClockInput>::with_capacity(8);
+        assert!(input
+            .try_enqueue(10, MidiClockMessage::Start.into())
+            .is_ok());
+        assert!(input
+            .try_enqueue(10, MidiClockMessage::Clock.into())
+            .is_ok());
+        root.input = input;
+        drop(log);
+        LOG.lock().clear();
+        run(&mut root, &mut children, 100);
+        assert_eq!(vec![(10.0, 0)], *LOG.lock());
+    }
+}
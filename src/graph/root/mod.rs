@@ -1,6 +1,7 @@
 use crate::{event::*, graph::GraphChildExec, tick::TickResched};
 
 pub mod clock;
+pub mod midi_clock;
 
 /// A trait for a graph root, this is executed the event schedule.
 pub trait GraphRootExec<E>: Send {
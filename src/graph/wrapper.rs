@@ -1,5 +1,5 @@
 use crate::event::EventEvalContext;
-use crate::graph::{GraphChildExec, GraphNode, GraphNodeExec};
+use crate::graph::{GraphChildExec, GraphLeafExec, GraphNode, GraphNodeExec};
 
 /// A container to hold a `GraphNodeExec` and `GraphChildExec` that implements `GraphNode` to
 /// call both.
@@ -39,3 +39,37 @@ where
         self.node.graph_exec(context, &self.children)
     }
 }
+
+/// A container to hold a `GraphLeafExec` that implements `GraphNode`, so leaves can be used as
+/// children.
+pub struct GraphLeafWrapper<L, E>
+where
+    L: GraphLeafExec<E>,
+    E: Send,
+{
+    pub leaf: L,
+    _phantom: core::marker::PhantomData<E>,
+}
+
+impl<L, E> GraphLeafWrapper<L, E>
+where
+    L: GraphLeafExec<E>,
+    E: Send,
+{
+    pub fn new(leaf: L) -> Self {
+        Self {
+            leaf,
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<L, E> GraphNode<E> for GraphLeafWrapper<L, E>
+where
+    L: GraphLeafExec<E>,
+    E: Send,
+{
+    fn node_exec(&self, context: &mut dyn EventEvalContext<E>) {
+        self.leaf.graph_exec(context)
+    }
+}
@@ -5,9 +5,15 @@ pub mod context;
 pub mod event;
 pub mod graph;
 pub mod param;
+pub mod position;
 pub mod pqueue;
+#[cfg(feature = "with_alloc")]
+pub mod render;
 pub mod sched;
+pub mod stats;
+pub mod tempo;
 pub mod tick;
+pub mod transport;
 
 #[cfg(feature = "float32")]
 pub type Float = f32;
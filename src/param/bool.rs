@@ -17,6 +17,7 @@ impl<const BYTES: usize> BoolArray<BYTES> {
         BYTES
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn byte(&self, index: usize) -> Result<u8, ()> {
         if index >= BYTES {
             Err(())
@@ -25,6 +26,7 @@ impl<const BYTES: usize> BoolArray<BYTES> {
         }
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn toggle(&self, key: usize) -> Result<bool, ()> {
         let byte = key / 8;
         if byte >= BYTES {
@@ -16,12 +16,14 @@ pub trait ParamSet<T>: Send + Sync {
     fn set(&self, value: T);
 }
 
+#[allow(clippy::len_without_is_empty)]
 pub trait ParamKeyValueGet<T>: Send + Sync {
     fn get_at(&self, key: usize) -> Option<T>;
     fn len(&self) -> Option<usize>;
     //should there be an indication if its sparce? ie Array v. HashMap
 }
 
+#[allow(clippy::len_without_is_empty)]
 pub trait ParamKeyValueSet<T>: Send + Sync {
     fn set_at(&self, key: usize, value: T) -> Result<(), T>;
     fn len(&self) -> Option<usize>;
@@ -15,14 +15,9 @@ impl OneShot {
 
 impl ParamGet<bool> for OneShot {
     fn get(&self) -> bool {
-        if let Ok(state) =
-            self.inner
-                .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-        {
-            state
-        } else {
-            false
-        }
+        self.inner
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .unwrap_or_default()
     }
 }
 
@@ -57,6 +57,7 @@ pub struct GetUnaryOp<I, O, F, P> {
     _phantom: PhantomData<fn() -> (I, O)>,
 }
 
+#[allow(clippy::type_complexity)]
 pub struct GetBinaryOp<IL, IR, O, F, PL, PR> {
     left: PL,
     right: PR,
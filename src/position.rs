@@ -0,0 +1,326 @@
+//! Musical positions, bars, beats and ticks
+//!
+//! Ticks here are the context ticks of a clock running at a `ClockData`'s `period_micros`, so
+//! there are `ppq` ticks per quarter note.
+use crate::{
+    clock::{Clock, ClockData},
+    tick::{offset_tick, tick_diff, to_offset, TickContext, TickResched, TickSched},
+    Float,
+};
+use core::fmt;
+
+/// A time signature, `beats` beats per bar, each a `1/unit` note.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeSignature {
+    pub beats: usize,
+    pub unit: usize,
+}
+
+/// A position, or a duration, in bars, beats and ticks, all zero based.
+///
+/// Positions made with a `TimeSignature` are normalized, `beat` is less than the beats per bar
+/// and `tick` less than the ticks per beat. `Display` shows the conventional one based
+/// `bar:beat:tick`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BarBeatTick {
+    pub bar: usize,
+    pub beat: usize,
+    pub tick: usize,
+}
+
+impl TimeSignature {
+    pub const fn new(beats: usize, unit: usize) -> Self {
+        assert!(beats > 0 && unit > 0);
+        Self { beats, unit }
+    }
+
+    /// Ticks per beat, 0 if a beat is shorter than a tick.
+    pub fn ticks_per_beat(&self, clock: &ClockData) -> usize {
+        clock.ppq() * 4 / core::cmp::max(self.unit, 1)
+    }
+
+    pub fn ticks_per_bar(&self, clock: &ClockData) -> usize {
+        self.ticks_per_beat(clock) * self.beats
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl BarBeatTick {
+    pub const fn new(bar: usize, beat: usize, tick: usize) -> Self {
+        Self { bar, beat, tick }
+    }
+
+    /// The position `ticks` context ticks after the start.
+    pub fn from_ticks(ticks: usize, sig: TimeSignature, clock: &ClockData) -> Self {
+        let per_beat = core::cmp::max(sig.ticks_per_beat(clock), 1);
+        let beats = ticks / per_beat;
+        let per_bar = core::cmp::max(sig.beats, 1);
+        Self {
+            bar: beats / per_bar,
+            beat: beats % per_bar,
+            tick: ticks % per_beat,
+        }
+    }
+
+    /// The number of context ticks from the start, beats and ticks past the end of a bar or
+    /// beat carry over.
+    pub fn to_ticks(&self, sig: TimeSignature, clock: &ClockData) -> usize {
+        let per_beat = sig.ticks_per_beat(clock);
+        (self.bar * sig.beats + self.beat) * per_beat + self.tick
+    }
+
+    /// Normalize, carrying ticks and beats past the end of a beat or bar.
+    pub fn normalize(&self, sig: TimeSignature, clock: &ClockData) -> Self {
+        Self::from_ticks(self.to_ticks(sig, clock), sig, clock)
+    }
+
+    /// The position of `tick` in `context`, if it is not before context tick 0.
+    ///
+    /// Absolute and relative ticks are converted to context ticks with the clock's period,
+    /// rounded to the nearest tick.
+    pub fn from_tick_sched(
+        tick: TickSched,
+        sig: TimeSignature,
+        clock: &ClockData,
+        context: &dyn TickContext,
+    ) -> Option<Self> {
+        let ticks = match tick {
+            //saturate like TickSched::to_absolute
+            TickSched::ContextAbsolute(t) => to_offset(t),
+            TickSched::ContextRelative(o) => {
+                to_offset(context.context_tick_now()).checked_add(o)?
+            }
+            TickSched::Absolute(_) | TickSched::Relative(_) => {
+                let zero = offset_tick(context.tick_now(), context.context_tick_offset());
+                let ticks = tick_diff(tick.to_absolute(context), zero);
+                if clock.period_micros() <= 0.0 {
+                    return None;
+                }
+                num_traits::Float::round(
+                    ticks as Float * context.tick_period_micros() / clock.period_micros(),
+                ) as isize
+            }
+        };
+        if ticks < 0 {
+            None
+        } else {
+            Some(Self::from_ticks(ticks as usize, sig, clock))
+        }
+    }
+
+    /// This position as a context absolute time.
+    pub fn to_tick_sched(&self, sig: TimeSignature, clock: &ClockData) -> TickSched {
+        TickSched::ContextAbsolute(self.to_ticks(sig, clock))
+    }
+
+    /// This position, as a duration from the start, in context ticks.
+    pub fn to_tick_resched(&self, sig: TimeSignature, clock: &ClockData) -> TickResched {
+        TickResched::ContextRelative(self.to_ticks(sig, clock))
+    }
+
+    /// Add a duration.
+    pub fn add(&self, duration: BarBeatTick, sig: TimeSignature, clock: &ClockData) -> Self {
+        Self::from_ticks(
+            self.to_ticks(sig, clock) + duration.to_ticks(sig, clock),
+            sig,
+            clock,
+        )
+    }
+
+    /// Subtract a duration, `None` if that would be before the start.
+    pub fn checked_sub(
+        &self,
+        duration: BarBeatTick,
+        sig: TimeSignature,
+        clock: &ClockData,
+    ) -> Option<Self> {
+        self.to_ticks(sig, clock)
+            .checked_sub(duration.to_ticks(sig, clock))
+            .map(|t| Self::from_ticks(t, sig, clock))
+    }
+
+    /// Add a number of context ticks, `None` if that would be before the start.
+    pub fn checked_add_ticks(
+        &self,
+        ticks: isize,
+        sig: TimeSignature,
+        clock: &ClockData,
+    ) -> Option<Self> {
+        let t = self.to_ticks(sig, clock);
+        let t = if ticks < 0 {
+            t.checked_sub(ticks.unsigned_abs())?
+        } else {
+            t.checked_add(ticks as usize)?
+        };
+        Some(Self::from_ticks(t, sig, clock))
+    }
+}
+
+impl fmt::Display for BarBeatTick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.bar + 1, self.beat + 1, self.tick)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext {
+        tick_now: usize,
+        context_tick_offset: isize,
+    }
+
+    impl TickContext for TestContext {
+        fn tick_now(&self) -> usize {
+            self.tick_now
+        }
+        fn ticks_per_second(&self) -> usize {
+            48000
+        }
+        fn context_tick_offset(&self) -> isize {
+            self.context_tick_offset
+        }
+    }
+
+    #[test]
+    fn ticks() {
+        let clock = ClockData::new(120.0, 96);
+        let sig = TimeSignature::default();
+        assert_eq!(96, sig.ticks_per_beat(&clock));
+        assert_eq!(384, sig.ticks_per_bar(&clock));
+
+        assert_eq!(
+            BarBeatTick::new(0, 0, 0),
+            BarBeatTick::from_ticks(0, sig, &clock)
+        );
+        assert_eq!(
+            BarBeatTick::new(0, 0, 95),
+            BarBeatTick::from_ticks(95, sig, &clock)
+        );
+        assert_eq!(
+            BarBeatTick::new(0, 1, 0),
+            BarBeatTick::from_ticks(96, sig, &clock)
+        );
+        assert_eq!(
+            BarBeatTick::new(2, 3, 5),
+            BarBeatTick::from_ticks(1061, sig, &clock)
+        );
+        assert_eq!(1061, BarBeatTick::new(2, 3, 5).to_ticks(sig, &clock));
+
+        //6/8, beats are eighths
+        let sig = TimeSignature::new(6, 8);
+        assert_eq!(48, sig.ticks_per_beat(&clock));
+        assert_eq!(288, sig.ticks_per_bar(&clock));
+        assert_eq!(
+            BarBeatTick::new(1, 5, 47),
+            BarBeatTick::from_ticks(575, sig, &clock)
+        );
+        assert_eq!(
+            BarBeatTick::new(2, 0, 0),
+            BarBeatTick::new(1, 5, 48).normalize(sig, &clock)
+        );
+        assert_eq!(
+            BarBeatTick::new(3, 1, 0),
+            BarBeatTick::new(0, 19, 0).normalize(sig, &clock)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn display() {
+        assert_eq!("3:4:5", format!("{}", BarBeatTick::new(2, 3, 5)));
+        assert_eq!("1:1:0", format!("{}", BarBeatTick::default()));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let clock = ClockData::new(120.0, 96);
+        let sig = TimeSignature::new(3, 4);
+        let pos = BarBeatTick::new(1, 2, 90);
+
+        assert_eq!(
+            BarBeatTick::new(2, 1, 0),
+            pos.add(BarBeatTick::new(0, 1, 6), sig, &clock)
+        );
+        assert_eq!(
+            Some(BarBeatTick::new(0, 2, 91)),
+            pos.checked_sub(BarBeatTick::new(0, 2, 95), sig, &clock)
+        );
+        assert_eq!(
+            None,
+            pos.checked_sub(BarBeatTick::new(2, 0, 0), sig, &clock)
+        );
+        assert_eq!(
+            Some(BarBeatTick::new(2, 0, 1)),
+            pos.checked_add_ticks(7, sig, &clock)
+        );
+        assert_eq!(
+            Some(BarBeatTick::new(1, 1, 95)),
+            pos.checked_add_ticks(-91, sig, &clock)
+        );
+        assert_eq!(None, pos.checked_add_ticks(-1000, sig, &clock));
+        assert!(BarBeatTick::new(1, 0, 0) > BarBeatTick::new(0, 2, 95));
+    }
+
+    #[test]
+    fn tick_sched() {
+        //a tick is 5208.33 micros, 250 ticks at 48k
+        let clock = ClockData::new(120.0, 96);
+        let sig = TimeSignature::default();
+        let pos = BarBeatTick::new(1, 1, 2);
+
+        assert_eq!(
+            TickSched::ContextAbsolute(482),
+            pos.to_tick_sched(sig, &clock)
+        );
+        assert_eq!(
+            TickResched::ContextRelative(482),
+            pos.to_tick_resched(sig, &clock)
+        );
+
+        //context tick 0 was 1000 ticks ago
+        let context = TestContext {
+            tick_now: 20000,
+            context_tick_offset: -1000,
+        };
+        assert_eq!(
+            Some(pos),
+            BarBeatTick::from_tick_sched(TickSched::ContextAbsolute(482), sig, &clock, &context)
+        );
+        assert_eq!(
+            Some(BarBeatTick::new(0, 0, 4)),
+            BarBeatTick::from_tick_sched(TickSched::Absolute(20000), sig, &clock, &context)
+        );
+        assert_eq!(
+            Some(BarBeatTick::new(0, 0, 8)),
+            BarBeatTick::from_tick_sched(TickSched::Relative(1000), sig, &clock, &context)
+        );
+        //nearest
+        assert_eq!(
+            Some(BarBeatTick::new(0, 0, 8)),
+            BarBeatTick::from_tick_sched(TickSched::Relative(1124), sig, &clock, &context)
+        );
+        assert_eq!(
+            None,
+            BarBeatTick::from_tick_sched(TickSched::Absolute(18000), sig, &clock, &context)
+        );
+        //saturates rather than wrapping to before context tick 0
+        assert_eq!(
+            Some(BarBeatTick::from_ticks(isize::MAX as usize, sig, &clock)),
+            BarBeatTick::from_tick_sched(
+                TickSched::ContextAbsolute(usize::MAX),
+                sig,
+                &clock,
+                &context
+            )
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Priority Queues
+//!
+//! Ticks are compared with `tick::tick_cmp` so queues keep working when the tick rolls over.
+use crate::{
+    spin::mutex::spin::SpinMutex,
+    tick::{tick_cmp, tick_lt},
+};
+use core::cmp::Ordering;
+
+pub mod spsc;
+pub mod staticheap;
+pub mod wheel;
+
+/// A handle to an enqueued item, that can be used to cancel it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TickHandle(usize);
+
+pub trait TickPriorityEnqueue<T>: Send {
+    /// Try to enqueue the item at the given tick
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T>;
+
+    /// Try to enqueue the item at the given tick, returning a handle that can cancel it, if the
+    /// queue supports cancellation.
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        self.try_enqueue(tick, value).map(|_| None)
+    }
+}
+
+pub trait TickPriorityDequeue<T>: Send {
+    /// Dequeue items, in order, with a tick less than `tick`, if there are any.
+    ///
+    /// "Less than" is evaluated with `tick::tick_lt` so it is rollover safe.
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)>;
+
+    /// The tick of the item `dequeue_lt` would return next, without dequeuing it.
+    fn peek_lt(&mut self, tick: usize) -> Option<usize>;
+
+    /// The number of items in the queue, if the queue can tell.
+    fn len_hint(&self) -> Option<usize> {
+        None
+    }
+}
+
+pub trait TickPriorityCancel<T>: Send {
+    /// Remove the pending item with the given `handle`, if it is still in the queue.
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)>;
+
+    /// Remove every pending item that `pred` returns true for, handing each one to `removed`.
+    ///
+    /// Returns the number of items removed.
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize;
+}
+
+impl TickHandle {
+    /// Create a handle from an item's sequence number.
+    pub fn new(seq: usize) -> Self {
+        Self(seq)
+    }
+
+    pub fn seq(&self) -> usize {
+        self.0
+    }
+}
+
+/// Share a queue, for instance to use the same queue as both the reader and writer of a
+/// `SchedExec`.
+impl<T, Q> TickPriorityEnqueue<T> for &SpinMutex<Q>
+where
+    Q: TickPriorityEnqueue<T>,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        self.lock().try_enqueue(tick, value)
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        self.lock().try_enqueue_handle(tick, value)
+    }
+}
+
+impl<T, Q> TickPriorityDequeue<T> for &SpinMutex<Q>
+where
+    Q: TickPriorityDequeue<T>,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+        self.lock().dequeue_lt(tick)
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.lock().peek_lt(tick)
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        self.lock().len_hint()
+    }
+}
+
+impl<T, Q> TickPriorityCancel<T> for &SpinMutex<Q>
+where
+    Q: TickPriorityCancel<T>,
+{
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+        self.lock().cancel(handle)
+    }
+
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        self.lock().cancel_where(pred, removed)
+    }
+}
+
+/// markers for ordering, work the fact that BinaryHeap is a max heap, so we reverse Ord for
+/// TickItem when using it.
+pub struct NormalOrd;
+pub struct ReverseOrd;
+
+/// An item with a tick, ordered by tick, then by item, then by insertion sequence number.
+///
+/// The sequence number makes the order of items on the same tick, that compare equal,
+/// deterministic. Queues should give each item they insert the next sequence number.
+pub struct TickItem<T, OrdOrder = NormalOrd> {
+    tick: usize,
+    seq: usize,
+    item: T,
+    _ord: core::marker::PhantomData<OrdOrder>,
+}
+
+#[cfg(feature = "std")]
+pub mod binaryheap {
+    use super::*;
+    pub struct BinaryHeapQueue<T> {
+        heap: std::collections::BinaryHeap<TickItem<T, ReverseOrd>>,
+        seq: usize,
+    }
+
+    impl<T> TickPriorityEnqueue<T> for BinaryHeapQueue<T>
+    where
+        T: Send + Ord,
+    {
+        fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+            self.try_enqueue_handle(tick, value).map(|_| ())
+        }
+
+        fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+            //don't allocate
+            if self.heap.len() >= self.heap.capacity() {
+                Err(value)
+            } else {
+                let seq = self.seq;
+                self.heap.push(TickItem::new(tick, seq, value));
+                self.seq = self.seq.wrapping_add(1);
+                Ok(Some(TickHandle::new(seq)))
+            }
+        }
+    }
+
+    impl<T> TickPriorityDequeue<T> for BinaryHeapQueue<T>
+    where
+        T: Send + Ord,
+    {
+        fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+            if let Some(t) = self.heap.peek() {
+                if tick_lt(t.tick(), tick) {
+                    self.heap.pop().map(|v| v.into())
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        }
+
+        fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+            self.heap
+                .peek()
+                .map(|t| t.tick())
+                .filter(|t| tick_lt(*t, tick))
+        }
+
+        fn len_hint(&self) -> Option<usize> {
+            Some(self.heap.len())
+        }
+    }
+
+    impl<T> TickPriorityCancel<T> for BinaryHeapQueue<T>
+    where
+        T: Send + Ord,
+    {
+        fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+            let mut item = None;
+            self.remove_where(&mut |i| i.seq() == handle.seq(), &mut |t, v| {
+                item = Some((t, v))
+            });
+            item
+        }
+
+        fn cancel_where(
+            &mut self,
+            pred: &mut dyn FnMut(usize, &T) -> bool,
+            removed: &mut dyn FnMut(usize, T),
+        ) -> usize {
+            self.remove_where(&mut |i| pred(i.tick(), i.item()), removed)
+        }
+    }
+
+    impl<T> BinaryHeapQueue<T>
+    where
+        T: Ord,
+    {
+        //reuses the heap's buffer, so this doesn't allocate
+        fn remove_where(
+            &mut self,
+            pred: &mut dyn FnMut(&TickItem<T, ReverseOrd>) -> bool,
+            removed: &mut dyn FnMut(usize, T),
+        ) -> usize {
+            let mut items = core::mem::take(&mut self.heap).into_vec();
+            let mut count = 0;
+            let mut index = 0;
+            while index < items.len() {
+                if pred(&items[index]) {
+                    let (t, v) = items.swap_remove(index).into();
+                    removed(t, v);
+                    count += 1;
+                } else {
+                    index += 1;
+                }
+            }
+            self.heap = items.into();
+            count
+        }
+
+        /// Create a BinaryHeapQueue with the given capacity
+        pub fn with_capacity(capacity: usize) -> Self {
+            Self {
+                heap: std::collections::BinaryHeap::with_capacity(capacity),
+                seq: 0,
+            }
+        }
+    }
+
+    impl<T> Default for BinaryHeapQueue<T>
+    where
+        T: Ord,
+    {
+        fn default() -> Self {
+            Self::with_capacity(1024)
+        }
+    }
+}
+
+impl<T, OrdOrder> TickItem<T, OrdOrder> {
+    pub fn new(tick: usize, seq: usize, item: T) -> Self {
+        Self {
+            tick,
+            seq,
+            item,
+            _ord: Default::default(),
+        }
+    }
+
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    pub fn item(&self) -> &T {
+        &self.item
+    }
+}
+
+/// Create an item with a zero sequence number.
+impl<T, OrdOrder> core::convert::From<(usize, T)> for TickItem<T, OrdOrder> {
+    fn from(item: (usize, T)) -> Self {
+        Self::new(item.0, 0, item.1)
+    }
+}
+
+impl<T, OrdOrder> core::convert::From<TickItem<T, OrdOrder>> for (usize, T) {
+    fn from(item: TickItem<T, OrdOrder>) -> Self {
+        (item.tick, item.item)
+    }
+}
+
+impl<T> Ord for TickItem<T, NormalOrd>
+where
+    T: Ord,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        match tick_cmp(self.tick, other.tick) {
+            Ordering::Less => Ordering::Less,
+            Ordering::Greater => Ordering::Greater,
+            Ordering::Equal => match self.item.cmp(&other.item) {
+                //sequence numbers wrap like ticks
+                Ordering::Equal => tick_cmp(self.seq, other.seq),
+                o => o,
+            },
+        }
+    }
+}
+
+impl<T> Ord for TickItem<T, ReverseOrd>
+where
+    T: Ord,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        match tick_cmp(self.tick, other.tick) {
+            Ordering::Less => Ordering::Greater,
+            Ordering::Greater => Ordering::Less,
+            Ordering::Equal => match self.item.cmp(&other.item) {
+                Ordering::Equal => tick_cmp(self.seq, other.seq).reverse(),
+                o => o.reverse(),
+            },
+        }
+    }
+}
+
+impl<T, OrdOrder> PartialOrd for TickItem<T, OrdOrder>
+where
+    T: Ord,
+    Self: Ord,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, OrdOrder> PartialEq for TickItem<T, OrdOrder>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.tick.eq(&other.tick) && self.seq.eq(&other.seq) && self.item.eq(&other.item)
+    }
+}
+
+impl<T, OrdOrder> Eq for TickItem<T, OrdOrder> where T: Eq {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ordered by key only, so equal keys need the sequence number to be deterministic.
+    #[derive(Debug)]
+    struct Keyed(usize, &'static str);
+
+    impl Ord for Keyed {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.cmp(&other.0)
+        }
+    }
+
+    impl PartialOrd for Keyed {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl PartialEq for Keyed {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Keyed {}
+
+    fn insertion_order<Q>(mut queue: Q)
+    where
+        Q: TickPriorityEnqueue<Keyed> + TickPriorityDequeue<Keyed>,
+    {
+        let items = [
+            (5, Keyed(0, "a")),
+            (5, Keyed(1, "off")),
+            (5, Keyed(0, "b")),
+            (2, Keyed(3, "first")),
+            (5, Keyed(0, "c")),
+            (5, Keyed(0, "d")),
+            (5, Keyed(1, "on")),
+            (5, Keyed(0, "e")),
+        ];
+        for (t, i) in IntoIterator::into_iter(items) {
+            assert!(queue.try_enqueue(t, i).is_ok());
+        }
+        assert_eq!(None, queue.peek_lt(2));
+        assert_eq!(Some(2), queue.peek_lt(10));
+        let mut order = [""; 8];
+        for o in order.iter_mut() {
+            *o = queue.dequeue_lt(10).unwrap().1 .1;
+        }
+        assert_eq!(["first", "a", "b", "c", "d", "e", "off", "on"], order);
+        assert!(queue.peek_lt(10).is_none());
+        assert!(queue.dequeue_lt(10).is_none());
+    }
+
+    #[test]
+    fn same_tick_order() {
+        insertion_order(staticheap::StaticTickHeap::<Keyed, 16>::new());
+        #[cfg(feature = "std")]
+        insertion_order(binaryheap::BinaryHeapQueue::with_capacity(16));
+    }
+
+    fn cancel_items<Q>(mut queue: Q)
+    where
+        Q: TickPriorityEnqueue<usize> + TickPriorityDequeue<usize> + TickPriorityCancel<usize>,
+    {
+        let mut handles = [None; 8];
+        for (i, h) in handles.iter_mut().enumerate() {
+            *h = queue.try_enqueue_handle(20 - i, i).unwrap();
+        }
+        let handle = handles[3].unwrap();
+        assert_eq!(Some((17, 3)), queue.cancel(handle));
+        assert_eq!(None, queue.cancel(handle));
+
+        let mut removed = [0; 8];
+        let mut count = 0;
+        assert_eq!(
+            3,
+            queue.cancel_where(&mut |_, v| v % 2 == 1, &mut |t, v| {
+                assert_eq!(20 - v, t);
+                removed[count] = v;
+                count += 1;
+            })
+        );
+        removed[..count].sort_unstable();
+        assert_eq!([1, 5, 7], removed[..count]);
+
+        //cancelled handles stay cancelled, others still work
+        assert_eq!(None, queue.cancel(handles[5].unwrap()));
+        assert_eq!(Some((14, 6)), queue.cancel(handles[6].unwrap()));
+
+        for v in &[4, 2, 0] {
+            assert_eq!(Some((20 - v, *v)), queue.dequeue_lt(100));
+        }
+        assert_eq!(None, queue.dequeue_lt(100));
+    }
+
+    #[test]
+    fn cancel() {
+        cancel_items(staticheap::StaticTickHeap::<usize, 8>::new());
+        #[cfg(feature = "std")]
+        cancel_items(binaryheap::BinaryHeapQueue::with_capacity(8));
+    }
+
+    #[test]
+    fn tick_item_order() {
+        let a: TickItem<usize> = TickItem::new(1, 4, 0);
+        let b: TickItem<usize> = TickItem::new(1, 5, 0);
+        let c: TickItem<usize> = TickItem::new(1, 0, 1);
+        let d: TickItem<usize> = TickItem::new(2, 0, 0);
+        assert!(a < b);
+        assert!(b < c);
+        assert!(c < d);
+
+        let a: TickItem<usize, ReverseOrd> = TickItem::new(1, 4, 0);
+        let b: TickItem<usize, ReverseOrd> = TickItem::new(1, 5, 0);
+        let c: TickItem<usize, ReverseOrd> = TickItem::new(1, 0, 1);
+        let d: TickItem<usize, ReverseOrd> = TickItem::new(2, 0, 0);
+        assert!(a > b);
+        assert!(b > c);
+        assert!(c > d);
+    }
+}
@@ -0,0 +1,400 @@
+//! Wait-free, fixed capacity, single producer single consumer queues.
+//!
+//! A `Queue` is split into a `Producer`, that can live on a control thread, and a `Consumer`,
+//! that can live on the real-time thread. Neither end locks or allocates.
+//!
+//! For ticked items, the producer implements `TickPriorityEnqueue` directly and a `TickConsumer`
+//! drains the queue into a local priority queue, so that items are dequeued in tick order. The
+//! `TickConsumer` also implements `TickPriorityEnqueue`, writing to its local queue, so events
+//! can be rescheduled from the consumer's thread. Without `std`, a `StaticTickHeap` can be used
+//! as the local queue.
+use super::*;
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering as AOrdering},
+};
+
+/// A fixed capacity, allocation free, single producer single consumer FIFO.
+pub struct Queue<T, const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+    //indices are kept in 0..2N so we can tell the difference between full and empty.
+    //head is only written by the consumer, tail is only written by the producer.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+/// The writing end of a `Queue`.
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+/// The reading end of a `Queue`.
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+/// The reading end of a `Queue` of ticked items that dequeues in tick order.
+///
+/// Items are moved from the queue into the `local` priority queue as they're read. If the local
+/// queue fills up, items stay in the `Queue` until there is room, and are only ordered against
+/// the local queue once they are read, so make the local queue large enough for every pending
+/// item.
+pub struct TickConsumer<'a, T, Q, const N: usize> {
+    consumer: Consumer<'a, (usize, T), N>,
+    local: Q,
+    pending: Option<(usize, T)>,
+}
+
+unsafe impl<T, const N: usize> Sync for Queue<T, N> where T: Send {}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        assert!(N > 0, "capacity must be greater than zero");
+        Self {
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split into a producer and consumer.
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        let queue: &Self = self;
+        (Producer { queue }, Consumer { queue })
+    }
+
+    /// The maximum number of items the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of items in the queue, this may be stale by the time it returns.
+    pub fn len(&self) -> usize {
+        Self::distance(
+            self.head.load(AOrdering::Acquire),
+            self.tail.load(AOrdering::Acquire),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn increment(index: usize) -> usize {
+        if index + 1 >= 2 * N {
+            0
+        } else {
+            index + 1
+        }
+    }
+
+    fn distance(head: usize, tail: usize) -> usize {
+        if tail >= head {
+            tail - head
+        } else {
+            2 * N - head + tail
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        let index = if index >= N { index - N } else { index };
+        self.buffer[index].get() as *mut T
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe {
+                core::ptr::drop_in_place(self.slot(head));
+            }
+            head = Self::increment(head);
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Producer<'a, T, N> {
+    /// Try to push a value onto the queue, returns the value if the queue is full.
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.queue.tail.load(AOrdering::Relaxed);
+        let head = self.queue.head.load(AOrdering::Acquire);
+        if Queue::<T, N>::distance(head, tail) >= N {
+            Err(value)
+        } else {
+            unsafe {
+                self.queue.slot(tail).write(value);
+            }
+            self.queue
+                .tail
+                .store(Queue::<T, N>::increment(tail), AOrdering::Release);
+            Ok(())
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= N
+    }
+}
+
+impl<'a, T, const N: usize> Consumer<'a, T, N> {
+    /// Pop the oldest value off the queue, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.queue.head.load(AOrdering::Relaxed);
+        let tail = self.queue.tail.load(AOrdering::Acquire);
+        if head == tail {
+            None
+        } else {
+            let value = unsafe { self.queue.slot(head).read() };
+            self.queue
+                .head
+                .store(Queue::<T, N>::increment(head), AOrdering::Release);
+            Some(value)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<'a, T, const N: usize> TickPriorityEnqueue<T> for Producer<'a, (usize, T), N>
+where
+    T: Send,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        self.try_push((tick, value)).map_err(|(_, value)| value)
+    }
+}
+
+impl<'a, T, Q, const N: usize> TickConsumer<'a, T, Q, N>
+where
+    T: Send,
+    Q: TickPriorityEnqueue<T> + TickPriorityDequeue<T>,
+{
+    pub fn new(consumer: Consumer<'a, (usize, T), N>, local: Q) -> Self {
+        Self {
+            consumer,
+            local,
+            pending: None,
+        }
+    }
+
+    /// Move as many items as will fit from the queue into the local priority queue.
+    pub fn drain(&mut self) {
+        if let Some((tick, value)) = self.pending.take() {
+            if let Err(value) = self.local.try_enqueue(tick, value) {
+                self.pending = Some((tick, value));
+                return;
+            }
+        }
+        while let Some((tick, value)) = self.consumer.pop() {
+            if let Err(value) = self.local.try_enqueue(tick, value) {
+                self.pending = Some((tick, value));
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, T, Q, const N: usize> TickPriorityEnqueue<T> for TickConsumer<'a, T, Q, N>
+where
+    T: Send,
+    Q: TickPriorityEnqueue<T> + TickPriorityDequeue<T>,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        self.local.try_enqueue(tick, value)
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        self.local.try_enqueue_handle(tick, value)
+    }
+}
+
+/// Cancels items in the local queue, after draining, items that are still in the spsc queue
+/// because the local queue is full are not cancelled.
+impl<'a, T, Q, const N: usize> TickPriorityCancel<T> for TickConsumer<'a, T, Q, N>
+where
+    T: Send,
+    Q: TickPriorityEnqueue<T> + TickPriorityDequeue<T> + TickPriorityCancel<T>,
+{
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+        self.drain();
+        self.local.cancel(handle)
+    }
+
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        self.drain();
+        self.local.cancel_where(pred, removed)
+    }
+}
+
+impl<'a, T, Q, const N: usize> TickPriorityDequeue<T> for TickConsumer<'a, T, Q, N>
+where
+    T: Send,
+    Q: TickPriorityEnqueue<T> + TickPriorityDequeue<T>,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+        self.drain();
+        match self.pending {
+            //the local queue is full, items in it up to the pending item's tick go first
+            Some((t, _)) if tick_lt(t, tick) => self
+                .local
+                .dequeue_lt(t.wrapping_add(1))
+                .or_else(|| self.pending.take()),
+            _ => self.local.dequeue_lt(tick),
+        }
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.drain();
+        match self.pending {
+            Some((t, _)) if tick_lt(t, tick) => self.local.peek_lt(t.wrapping_add(1)).or(Some(t)),
+            _ => self.local.peek_lt(tick),
+        }
+    }
+
+    /// Includes the items still in the spsc queue.
+    fn len_hint(&self) -> Option<usize> {
+        let pending = usize::from(self.pending.is_some());
+        self.local
+            .len_hint()
+            .map(|len| len + self.consumer.queue.len() + pending)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::pqueue::binaryheap::BinaryHeapQueue;
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    #[test]
+    fn fifo() {
+        let mut queue: Queue<usize, 4> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        assert_eq!(None, c.pop());
+        for round in 0..10 {
+            for i in 0..4 {
+                assert!(p.try_push(round * 10 + i).is_ok());
+            }
+            assert!(p.is_full());
+            assert_eq!(Err(100), p.try_push(100));
+            for i in 0..4 {
+                assert_eq!(Some(round * 10 + i), c.pop());
+            }
+            assert!(c.is_empty());
+            assert_eq!(None, c.pop());
+
+            //partial fills so the indices move around the buffer
+            assert!(p.try_push(round).is_ok());
+            assert_eq!(Some(round), c.pop());
+        }
+    }
+
+    #[test]
+    fn drops_remaining() {
+        let item = Arc::new(());
+        {
+            let mut queue: Queue<Arc<()>, 8> = Queue::new();
+            let (mut p, mut c) = queue.split();
+            for _ in 0..5 {
+                assert!(p.try_push(item.clone()).is_ok());
+            }
+            assert!(c.pop().is_some());
+            assert_eq!(5, Arc::strong_count(&item));
+        }
+        assert_eq!(1, Arc::strong_count(&item));
+    }
+
+    #[test]
+    fn tick_order() {
+        let mut queue: Queue<(usize, usize), 8> = Queue::new();
+        let (mut p, c) = queue.split();
+        let mut c = TickConsumer::new(c, BinaryHeapQueue::with_capacity(4));
+
+        for t in &[20, 5, 12, 1, 7, 30] {
+            assert!(p.try_enqueue(*t, *t * 2).is_ok());
+        }
+        //local queue only holds 4, the rest wait in the shared queue
+        assert_eq!(Some((1, 2)), c.dequeue_lt(10));
+        assert_eq!(Some((5, 10)), c.dequeue_lt(10));
+        assert_eq!(Some((7, 14)), c.dequeue_lt(10));
+        assert_eq!(None, c.dequeue_lt(10));
+
+        //local reschedule
+        assert!(c.try_enqueue(11, 0).is_ok());
+        assert_eq!(Some((11, 0)), c.dequeue_lt(100));
+        assert_eq!(Some((12, 24)), c.dequeue_lt(100));
+        assert_eq!(Some((20, 40)), c.dequeue_lt(100));
+        assert_eq!(Some((30, 60)), c.dequeue_lt(100));
+        assert_eq!(None, c.dequeue_lt(100));
+    }
+
+    #[test]
+    fn local_full() {
+        let mut queue: Queue<(usize, usize), 8> = Queue::new();
+        let (mut p, c) = queue.split();
+        let mut c = TickConsumer::new(c, BinaryHeapQueue::with_capacity(2));
+
+        for t in &[10, 11, 1, 5, 12] {
+            assert!(p.try_enqueue(*t, *t).is_ok());
+        }
+        //1 is held while the local queue is full, it still comes out first
+        assert_eq!(Some(1), c.peek_lt(100));
+        assert_eq!(Some((1, 1)), c.dequeue_lt(100));
+        assert_eq!(Some((5, 5)), c.dequeue_lt(100));
+        assert_eq!(None, c.dequeue_lt(10));
+        assert_eq!(Some((10, 10)), c.dequeue_lt(100));
+        assert_eq!(Some((11, 11)), c.dequeue_lt(100));
+        assert_eq!(Some((12, 12)), c.dequeue_lt(100));
+        assert_eq!(None, c.dequeue_lt(100));
+    }
+
+    #[test]
+    fn threaded() {
+        const COUNT: usize = 10_000;
+        let mut queue: Queue<(usize, usize), 16> = Queue::new();
+        let (mut p, c) = queue.split();
+        let mut c = TickConsumer::new(c, BinaryHeapQueue::with_capacity(16));
+        let received = AtomicUsize::new(0);
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..COUNT {
+                    let mut v = i;
+                    while let Err(r) = p.try_enqueue(i, v) {
+                        v = r;
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            s.spawn(|| {
+                let mut next = 0;
+                while next < COUNT {
+                    if let Some((t, v)) = c.dequeue_lt(COUNT) {
+                        assert_eq!(t, v);
+                        assert_eq!(next, v);
+                        next += 1;
+                        received.fetch_add(1, AOrdering::SeqCst);
+                    } else {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+        });
+        assert_eq!(COUNT, received.load(AOrdering::SeqCst));
+    }
+}
@@ -0,0 +1,368 @@
+//! A fixed capacity priority queue with inline storage, for use without `std` or `alloc`.
+use super::*;
+use core::mem::MaybeUninit;
+
+/// A binary heap, stored inline, that holds up to `N` items.
+///
+/// Items are ordered as `TickItem<T, ReverseOrd>`, so it dequeues in the same order as
+/// `BinaryHeapQueue`.
+pub struct StaticTickHeap<T, const N: usize> {
+    items: [MaybeUninit<TickItem<T, ReverseOrd>>; N],
+    len: usize,
+    seq: usize,
+}
+
+impl<T, const N: usize> StaticTickHeap<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            items: [const { MaybeUninit::uninit() }; N],
+            len: 0,
+            seq: 0,
+        }
+    }
+
+    /// The maximum number of items the heap can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The tick of the next item to be dequeued, if there is one.
+    pub fn peek_tick(&self) -> Option<usize> {
+        if self.len > 0 {
+            Some(self.item(0).tick())
+        } else {
+            None
+        }
+    }
+
+    /// Remove and drop all the items.
+    pub fn clear(&mut self) {
+        let len = self.len;
+        self.len = 0;
+        for item in self.items[..len].iter_mut() {
+            unsafe {
+                item.assume_init_drop();
+            }
+        }
+    }
+
+    fn item(&self, index: usize) -> &TickItem<T, ReverseOrd> {
+        debug_assert!(index < self.len);
+        unsafe { self.items[index].assume_init_ref() }
+    }
+}
+
+impl<T, const N: usize> StaticTickHeap<T, N>
+where
+    T: Ord,
+{
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.item(index) <= self.item(parent) {
+                break;
+            }
+            self.items.swap(index, parent);
+            index = parent;
+        }
+    }
+
+    fn remove_where(
+        &mut self,
+        pred: &mut dyn FnMut(&TickItem<T, ReverseOrd>) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        let mut count = 0;
+        let mut index = 0;
+        while index < self.len {
+            if pred(self.item(index)) {
+                self.len -= 1;
+                self.items.swap(index, self.len);
+                let (t, v) = unsafe { self.items[self.len].assume_init_read() }.into();
+                removed(t, v);
+                count += 1;
+            } else {
+                index += 1;
+            }
+        }
+        if count > 0 {
+            for index in (0..self.len / 2).rev() {
+                self.sift_down(index);
+            }
+        }
+        count
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let left = 2 * index + 1;
+            if left >= self.len {
+                break;
+            }
+            let right = left + 1;
+            let child = if right < self.len && self.item(right) > self.item(left) {
+                right
+            } else {
+                left
+            };
+            if self.item(index) >= self.item(child) {
+                break;
+            }
+            self.items.swap(index, child);
+            index = child;
+        }
+    }
+}
+
+impl<T, const N: usize> TickPriorityEnqueue<T> for StaticTickHeap<T, N>
+where
+    T: Send + Ord,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        self.try_enqueue_handle(tick, value).map(|_| ())
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        if self.len >= N {
+            Err(value)
+        } else {
+            let seq = self.seq;
+            self.items[self.len] = MaybeUninit::new(TickItem::new(tick, seq, value));
+            self.seq = self.seq.wrapping_add(1);
+            self.len += 1;
+            self.sift_up(self.len - 1);
+            Ok(Some(TickHandle::new(seq)))
+        }
+    }
+}
+
+impl<T, const N: usize> TickPriorityCancel<T> for StaticTickHeap<T, N>
+where
+    T: Send + Ord,
+{
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+        let mut item = None;
+        self.remove_where(&mut |i| i.seq() == handle.seq(), &mut |t, v| {
+            item = Some((t, v))
+        });
+        item
+    }
+
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        self.remove_where(&mut |i| pred(i.tick(), i.item()), removed)
+    }
+}
+
+impl<T, const N: usize> TickPriorityDequeue<T> for StaticTickHeap<T, N>
+where
+    T: Send + Ord,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+        match self.peek_tick() {
+            Some(t) if tick_lt(t, tick) => {
+                self.len -= 1;
+                self.items.swap(0, self.len);
+                let item = unsafe { self.items[self.len].assume_init_read() };
+                self.sift_down(0);
+                Some(item.into())
+            }
+            _ => None,
+        }
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.peek_tick().filter(|t| tick_lt(*t, tick))
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<T, const N: usize> Default for StaticTickHeap<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for StaticTickHeap<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event::{EventEval, EventEvalContext},
+        pqueue::spsc::{Queue, TickConsumer},
+        sched::SchedExec,
+        spin::mutex::spin::SpinMutex,
+        tick::TickResched,
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering as AOrdering};
+
+    #[test]
+    fn order() {
+        let mut heap: StaticTickHeap<usize, 16> = StaticTickHeap::new();
+        assert_eq!(16, heap.capacity());
+        assert!(heap.is_empty());
+        assert_eq!(None, heap.dequeue_lt(1000));
+
+        let items = [
+            (20, 0),
+            (5, 3),
+            (5, 1),
+            (12, 9),
+            (1, 0),
+            (5, 2),
+            (30, 4),
+            (0, 7),
+        ];
+        for (t, v) in items.iter() {
+            assert!(heap.try_enqueue(*t, *v).is_ok());
+        }
+        assert_eq!(items.len(), heap.len());
+        assert_eq!(Some(0), heap.peek_tick());
+
+        assert_eq!(Some((0, 7)), heap.dequeue_lt(6));
+        assert_eq!(Some((1, 0)), heap.dequeue_lt(6));
+        //equal ticks come out in item order
+        assert_eq!(Some((5, 1)), heap.dequeue_lt(6));
+        assert_eq!(Some((5, 2)), heap.dequeue_lt(6));
+        assert_eq!(Some((5, 3)), heap.dequeue_lt(6));
+        assert_eq!(None, heap.dequeue_lt(6));
+        assert_eq!(None, heap.dequeue_lt(12));
+        assert_eq!(Some((12, 9)), heap.dequeue_lt(13));
+        assert_eq!(Some((20, 0)), heap.dequeue_lt(1000));
+        assert_eq!(Some((30, 4)), heap.dequeue_lt(1000));
+        assert_eq!(None, heap.dequeue_lt(1000));
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn full() {
+        let mut heap: StaticTickHeap<usize, 4> = StaticTickHeap::new();
+        for i in 0..4 {
+            assert!(heap.try_enqueue(10 - i, i).is_ok());
+        }
+        assert_eq!(Err(100), heap.try_enqueue(0, 100));
+        assert_eq!(Some((7, 3)), heap.dequeue_lt(1000));
+        assert!(heap.try_enqueue(0, 100).is_ok());
+        assert_eq!(Some((0, 100)), heap.dequeue_lt(1000));
+    }
+
+    #[test]
+    fn rollover() {
+        let mut heap: StaticTickHeap<usize, 8> = StaticTickHeap::new();
+        for (i, t) in [3, usize::MAX - 1, 0, usize::MAX - 3].iter().enumerate() {
+            assert!(heap.try_enqueue(*t, i).is_ok());
+        }
+        assert_eq!(Some((usize::MAX - 3, 3)), heap.dequeue_lt(usize::MAX - 2));
+        assert_eq!(None, heap.dequeue_lt(usize::MAX - 2));
+        assert_eq!(Some((usize::MAX - 1, 1)), heap.dequeue_lt(2));
+        assert_eq!(Some((0, 2)), heap.dequeue_lt(2));
+        assert_eq!(None, heap.dequeue_lt(2));
+        assert_eq!(Some((3, 0)), heap.dequeue_lt(4));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn matches_binary_heap() {
+        use crate::pqueue::binaryheap::BinaryHeapQueue;
+
+        let mut heap: StaticTickHeap<usize, 256> = StaticTickHeap::new();
+        let mut bheap: BinaryHeapQueue<usize> = BinaryHeapQueue::with_capacity(256);
+
+        //simple LCG so the sequence is repeatable
+        let mut seed = 1234usize;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+            seed
+        };
+        for _ in 0..200 {
+            let (t, v) = (next() % 50, next() % 10);
+            assert!(heap.try_enqueue(t, v).is_ok());
+            assert!(bheap.try_enqueue(t, v).is_ok());
+        }
+        let mut tick = 0;
+        while !heap.is_empty() {
+            tick += 5;
+            loop {
+                let v = heap.dequeue_lt(tick);
+                assert_eq!(bheap.dequeue_lt(tick), v);
+                if v.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn drops() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct DropCount;
+        impl Drop for DropCount {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, AOrdering::SeqCst);
+            }
+        }
+
+        {
+            let mut heap: StaticTickHeap<DropCount, 8> = StaticTickHeap::new();
+            for i in 0..5 {
+                assert!(heap.try_enqueue(i, DropCount).is_ok());
+            }
+            assert!(heap.dequeue_lt(1).is_some());
+            assert_eq!(1, DROPS.load(AOrdering::SeqCst));
+        }
+        assert_eq!(5, DROPS.load(AOrdering::SeqCst));
+    }
+
+    static EVAL_CNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    struct CountEvent;
+
+    impl EventEval<CountEvent> for CountEvent {
+        fn event_eval(&mut self, _context: &mut dyn EventEvalContext<CountEvent>) -> TickResched {
+            EVAL_CNT.fetch_add(1, AOrdering::SeqCst);
+            TickResched::Relative(4)
+        }
+    }
+
+    #[test]
+    fn sched() {
+        //no std or alloc needed
+        let mut queue: Queue<(usize, CountEvent), 4> = Queue::new();
+        let (mut producer, consumer) = queue.split();
+        let consumer = SpinMutex::new(TickConsumer::new(
+            consumer,
+            StaticTickHeap::<CountEvent, 8>::new(),
+        ));
+        let mut sched = SchedExec::new(&consumer, &consumer);
+
+        assert!(producer.try_enqueue(2, CountEvent).is_ok());
+        sched.run(2, 48000);
+        assert_eq!(0, EVAL_CNT.load(AOrdering::SeqCst));
+        sched.run(1, 48000);
+        assert_eq!(1, EVAL_CNT.load(AOrdering::SeqCst));
+        sched.run(16, 48000);
+        assert_eq!(5, EVAL_CNT.load(AOrdering::SeqCst));
+    }
+}
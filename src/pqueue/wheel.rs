@@ -0,0 +1,668 @@
+//! A hierarchical timer wheel, for dense schedules.
+//!
+//! The wheel has a level for every 6 bits of the tick, each with 64 slots. An item goes into the
+//! level of the highest bit where its tick differs from the wheel's current time, so inserting
+//! is O(1). When the lower levels run out, the next occupied slot of a higher level is cascaded
+//! down, each item moves down at most once per level, so dequeuing is amortized O(1) too.
+//!
+//! Items are stored inline, in a fixed size pool, so the wheel never allocates.
+use super::*;
+use core::mem::MaybeUninit;
+
+const BITS: usize = 6;
+const SLOTS: usize = 1 << BITS;
+const MASK: usize = SLOTS - 1;
+const LEVELS: usize = (usize::BITS as usize).div_ceil(BITS);
+const NIL: usize = usize::MAX;
+
+#[derive(Copy, Clone)]
+struct List {
+    head: usize,
+    tail: usize,
+}
+
+impl List {
+    const EMPTY: Self = Self {
+        head: NIL,
+        tail: NIL,
+    };
+}
+
+#[derive(Copy, Clone)]
+enum Loc {
+    Late,
+    Slot(usize, usize),
+}
+
+/// A hierarchical timer wheel that holds up to `N` items.
+///
+/// Items dequeue in the same order as `BinaryHeapQueue`, by tick, then item, then insertion
+/// order. To keep that order within a tick, the lowest level keeps its slots sorted, this is O(1)
+/// unless items on the same tick are enqueued out of item order. Items enqueued before the
+/// wheel's current time, which is never later than the tick of the last dequeued item, are kept
+/// in a sorted list.
+///
+/// Prefer `StaticTickHeap` or `BinaryHeapQueue` for small schedules, the wheel is much larger
+/// and only pays off when there are many pending items.
+pub struct TimerWheel<T, const N: usize> {
+    items: [MaybeUninit<TickItem<T>>; N],
+    next: [usize; N],
+    //head of the list of freed nodes
+    free: usize,
+    //nodes from here up have never been used
+    unused: usize,
+    slots: [[List; SLOTS]; LEVELS],
+    occupied: [u64; LEVELS],
+    late: List,
+    elapsed: usize,
+    len: usize,
+    seq: usize,
+}
+
+fn level_for(elapsed: usize, tick: usize) -> usize {
+    let masked = (elapsed ^ tick) | MASK;
+    (usize::BITS - 1 - masked.leading_zeros()) as usize / BITS
+}
+
+fn slot_for(tick: usize, level: usize) -> usize {
+    (tick >> (level * BITS)) & MASK
+}
+
+//the first tick of the given slot, in the current rotation of its level
+fn block_start(elapsed: usize, level: usize, slot: usize) -> usize {
+    let shift = level * BITS;
+    let upper_shift = (shift + BITS) as u32;
+    let upper = elapsed
+        .checked_shr(upper_shift)
+        .map_or(0, |u| u.checked_shl(upper_shift).unwrap_or(0));
+    upper | (slot << shift)
+}
+
+//the first occupied slot at or after `from`
+fn first_from(occupied: u64, from: usize) -> Option<usize> {
+    let bits = occupied.checked_shr(from as u32).unwrap_or(0);
+    if bits == 0 {
+        None
+    } else {
+        Some(from + bits.trailing_zeros() as usize)
+    }
+}
+
+impl<T, const N: usize> TimerWheel<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            items: [const { MaybeUninit::uninit() }; N],
+            next: [NIL; N],
+            free: NIL,
+            unused: 0,
+            slots: [[List::EMPTY; SLOTS]; LEVELS],
+            occupied: [0; LEVELS],
+            late: List::EMPTY,
+            elapsed: 0,
+            len: 0,
+            seq: 0,
+        }
+    }
+
+    /// The maximum number of items the wheel can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Remove and drop all the items.
+    pub fn clear(&mut self) {
+        for loc in Self::locs() {
+            let mut index = self.list(loc).head;
+            while index != NIL {
+                unsafe {
+                    self.items[index].assume_init_drop();
+                }
+                index = self.next[index];
+            }
+        }
+        self.free = NIL;
+        self.unused = 0;
+        self.slots = [[List::EMPTY; SLOTS]; LEVELS];
+        self.occupied = [0; LEVELS];
+        self.late = List::EMPTY;
+        self.len = 0;
+    }
+
+    fn locs() -> impl Iterator<Item = Loc> {
+        core::iter::once(Loc::Late)
+            .chain((0..LEVELS).flat_map(|level| (0..SLOTS).map(move |slot| Loc::Slot(level, slot))))
+    }
+
+    fn item(&self, index: usize) -> &TickItem<T> {
+        unsafe { self.items[index].assume_init_ref() }
+    }
+
+    fn list(&self, loc: Loc) -> List {
+        match loc {
+            Loc::Late => self.late,
+            Loc::Slot(level, slot) => self.slots[level][slot],
+        }
+    }
+
+    fn set_list(&mut self, loc: Loc, list: List) {
+        match loc {
+            Loc::Late => self.late = list,
+            Loc::Slot(level, slot) => {
+                self.slots[level][slot] = list;
+                if list.head == NIL {
+                    self.occupied[level] &= !(1 << slot);
+                } else {
+                    self.occupied[level] |= 1 << slot;
+                }
+            }
+        }
+    }
+
+    fn push_back(&mut self, loc: Loc, index: usize) {
+        let mut list = self.list(loc);
+        self.next[index] = NIL;
+        if list.head == NIL {
+            list.head = index;
+        } else {
+            self.next[list.tail] = index;
+        }
+        list.tail = index;
+        self.set_list(loc, list);
+    }
+
+    fn pop_front(&mut self, loc: Loc) -> usize {
+        let mut list = self.list(loc);
+        let index = list.head;
+        list.head = self.next[index];
+        if list.head == NIL {
+            list.tail = NIL;
+        }
+        self.set_list(loc, list);
+        index
+    }
+
+    fn take(&mut self, index: usize) -> TickItem<T> {
+        let item = unsafe { self.items[index].assume_init_read() };
+        self.next[index] = self.free;
+        self.free = index;
+        self.len -= 1;
+        item
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if self.free != NIL {
+            let index = self.free;
+            self.free = self.next[index];
+            Some(index)
+        } else if self.unused < N {
+            self.unused += 1;
+            Some(self.unused - 1)
+        } else {
+            None
+        }
+    }
+
+    //find the lowest occupied slot above the current time, at the lowest occupied level above 0
+    fn next_block(&self) -> Option<(usize, usize)> {
+        for level in 1..LEVELS {
+            let current = slot_for(self.elapsed, level);
+            let occupied = self.occupied[level];
+            if let Some(slot) = first_from(occupied, current + 1) {
+                return Some((level, slot));
+            }
+            //only the top level wraps, these items are after the tick rolls over
+            if level == LEVELS - 1 && occupied != 0 {
+                return Some((level, occupied.trailing_zeros() as usize));
+            }
+        }
+        None
+    }
+}
+
+impl<T, const N: usize> TimerWheel<T, N>
+where
+    T: Ord,
+{
+    //put the node in the list for its tick, relative to elapsed
+    fn place(&mut self, index: usize) {
+        let tick = self.item(index).tick();
+        if tick_lt(tick, self.elapsed) {
+            self.insert_sorted(Loc::Late, index);
+        } else {
+            let level = level_for(self.elapsed, tick);
+            let loc = Loc::Slot(level, slot_for(tick, level));
+            if level == 0 {
+                self.insert_sorted(loc, index);
+            } else {
+                self.push_back(loc, index);
+            }
+        }
+    }
+
+    fn cascade(&mut self, level: usize, slot: usize) {
+        let mut index = self.slots[level][slot].head;
+        self.set_list(Loc::Slot(level, slot), List::EMPTY);
+        while index != NIL {
+            let next = self.next[index];
+            self.place(index);
+            index = next;
+        }
+    }
+
+    fn insert_sorted(&mut self, loc: Loc, index: usize) {
+        let mut list = self.list(loc);
+        if list.head == NIL || self.item(list.tail) <= self.item(index) {
+            self.push_back(loc, index);
+            return;
+        }
+        let mut prev = NIL;
+        let mut cur = list.head;
+        while cur != NIL && self.item(cur) <= self.item(index) {
+            prev = cur;
+            cur = self.next[cur];
+        }
+        //cur is not NIL, the tail is greater than the new item
+        self.next[index] = cur;
+        if prev == NIL {
+            list.head = index;
+        } else {
+            self.next[prev] = index;
+        }
+        self.set_list(loc, list);
+    }
+
+    //where the next item before tick is, cascading down to it
+    fn next_lt(&mut self, tick: usize) -> Option<Loc> {
+        //late items come before everything in the wheel
+        if self.late.head != NIL {
+            return if tick_lt(self.item(self.late.head).tick(), tick) {
+                Some(Loc::Late)
+            } else {
+                None
+            };
+        }
+        loop {
+            if let Some(slot) = first_from(self.occupied[0], slot_for(self.elapsed, 0)) {
+                let t = block_start(self.elapsed, 0, slot);
+                if !tick_lt(t, tick) {
+                    return None;
+                }
+                self.elapsed = t;
+                return Some(Loc::Slot(0, slot));
+            }
+            match self.next_block() {
+                Some((level, slot)) => {
+                    let start = block_start(self.elapsed, level, slot);
+                    //don't move past tick, items may still be enqueued before it
+                    if !tick_lt(start, tick) {
+                        return None;
+                    }
+                    self.elapsed = start;
+                    self.cascade(level, slot);
+                }
+                None => {
+                    //empty, keep up with time so new items aren't seen as late
+                    self.elapsed = tick;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn remove_where(
+        &mut self,
+        pred: &mut dyn FnMut(&TickItem<T>) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        let mut count = 0;
+        for loc in Self::locs() {
+            let mut list = self.list(loc);
+            let mut prev = NIL;
+            let mut index = list.head;
+            while index != NIL {
+                let next = self.next[index];
+                if pred(self.item(index)) {
+                    if prev == NIL {
+                        list.head = next;
+                    } else {
+                        self.next[prev] = next;
+                    }
+                    if list.tail == index {
+                        list.tail = prev;
+                    }
+                    let (t, v) = self.take(index).into();
+                    removed(t, v);
+                    count += 1;
+                } else {
+                    prev = index;
+                }
+                index = next;
+            }
+            self.set_list(loc, list);
+        }
+        count
+    }
+}
+
+impl<T, const N: usize> TickPriorityEnqueue<T> for TimerWheel<T, N>
+where
+    T: Send + Ord,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        self.try_enqueue_handle(tick, value).map(|_| ())
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        match self.alloc() {
+            None => Err(value),
+            Some(index) => {
+                let seq = self.seq;
+                self.items[index] = MaybeUninit::new(TickItem::new(tick, seq, value));
+                self.seq = self.seq.wrapping_add(1);
+                self.len += 1;
+                self.place(index);
+                Ok(Some(TickHandle::new(seq)))
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> TickPriorityDequeue<T> for TimerWheel<T, N>
+where
+    T: Send + Ord,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+        self.next_lt(tick).map(|loc| {
+            let index = self.pop_front(loc);
+            self.take(index).into()
+        })
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.next_lt(tick)
+            .map(|loc| self.item(self.list(loc).head).tick())
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<T, const N: usize> TickPriorityCancel<T> for TimerWheel<T, N>
+where
+    T: Send + Ord,
+{
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+        let mut item = None;
+        self.remove_where(&mut |i| i.seq() == handle.seq(), &mut |t, v| {
+            item = Some((t, v))
+        });
+        item
+    }
+
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        self.remove_where(&mut |i| pred(i.tick(), i.item()), removed)
+    }
+}
+
+impl<T, const N: usize> Default for TimerWheel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for TimerWheel<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering as AOrdering};
+
+    #[test]
+    fn order() {
+        let mut wheel: TimerWheel<usize, 16> = TimerWheel::new();
+        assert_eq!(16, wheel.capacity());
+        assert!(wheel.is_empty());
+        assert_eq!(None, wheel.dequeue_lt(1000));
+
+        let items = [
+            (20, 0),
+            (5, 3),
+            (5, 1),
+            (12, 9),
+            (1, 0),
+            (5, 2),
+            (30, 4),
+            (0, 7),
+            (70_000, 1),
+            (4096, 5),
+        ];
+        for (t, v) in items.iter() {
+            assert!(wheel.try_enqueue(*t, *v).is_ok());
+        }
+        assert_eq!(items.len(), wheel.len());
+
+        assert_eq!(Some((0, 7)), wheel.dequeue_lt(6));
+        assert_eq!(Some((1, 0)), wheel.dequeue_lt(6));
+        //equal ticks come out in item order
+        assert_eq!(Some((5, 1)), wheel.dequeue_lt(6));
+        assert_eq!(Some((5, 2)), wheel.dequeue_lt(6));
+        assert_eq!(Some((5, 3)), wheel.dequeue_lt(6));
+        assert_eq!(None, wheel.dequeue_lt(6));
+        assert_eq!(None, wheel.dequeue_lt(12));
+        assert_eq!(None, wheel.peek_lt(12));
+        assert_eq!(Some(12), wheel.peek_lt(13));
+        assert_eq!(Some((12, 9)), wheel.dequeue_lt(13));
+        assert_eq!(Some((20, 0)), wheel.dequeue_lt(1000));
+        assert_eq!(Some((30, 4)), wheel.dequeue_lt(1000));
+        assert_eq!(None, wheel.dequeue_lt(1000));
+        assert_eq!(None, wheel.dequeue_lt(4096));
+        //peeking cascades, but leaves the item
+        assert_eq!(Some(4096), wheel.peek_lt(4097));
+        assert_eq!(Some((4096, 5)), wheel.dequeue_lt(4097));
+        assert_eq!(Some((70_000, 1)), wheel.dequeue_lt(usize::MAX / 2));
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn same_tick_insertion_order() {
+        #[derive(Debug)]
+        struct Keyed(usize, &'static str);
+
+        impl Ord for Keyed {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+        impl PartialOrd for Keyed {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl PartialEq for Keyed {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Keyed {}
+
+        let mut wheel: TimerWheel<Keyed, 8> = TimerWheel::new();
+        //far enough away to cascade through a couple of levels
+        for (k, name) in &[(1, "a"), (0, "b"), (1, "c"), (0, "d"), (1, "e")] {
+            assert!(wheel.try_enqueue(5000, Keyed(*k, name)).is_ok());
+        }
+        for name in &["b", "d", "a", "c", "e"] {
+            assert_eq!(Some(*name), wheel.dequeue_lt(5001).map(|(_, k)| k.1));
+        }
+        assert!(wheel.dequeue_lt(5001).is_none());
+    }
+
+    #[test]
+    fn full() {
+        let mut wheel: TimerWheel<usize, 4> = TimerWheel::new();
+        for i in 0..4 {
+            assert!(wheel.try_enqueue(10 - i, i).is_ok());
+        }
+        assert_eq!(Err(100), wheel.try_enqueue(0, 100));
+        assert_eq!(Some((7, 3)), wheel.dequeue_lt(1000));
+        assert!(wheel.try_enqueue(8, 100).is_ok());
+        assert_eq!(Some((8, 2)), wheel.dequeue_lt(1000));
+        assert_eq!(Some((8, 100)), wheel.dequeue_lt(1000));
+    }
+
+    #[test]
+    fn late() {
+        let mut wheel: TimerWheel<usize, 8> = TimerWheel::new();
+        assert!(wheel.try_enqueue(100, 0).is_ok());
+        assert!(wheel.try_enqueue(200, 1).is_ok());
+        assert_eq!(Some((100, 0)), wheel.dequeue_lt(150));
+
+        //before the last dequeued item, these still come out in order, before the rest
+        assert!(wheel.try_enqueue(60, 2).is_ok());
+        assert!(wheel.try_enqueue(20, 3).is_ok());
+        assert!(wheel.try_enqueue(150, 4).is_ok());
+        assert_eq!(Some((20, 3)), wheel.dequeue_lt(30));
+        assert_eq!(None, wheel.dequeue_lt(30));
+        assert_eq!(Some((60, 2)), wheel.dequeue_lt(1000));
+        assert_eq!(Some((150, 4)), wheel.dequeue_lt(1000));
+        assert_eq!(Some((200, 1)), wheel.dequeue_lt(1000));
+        assert_eq!(None, wheel.dequeue_lt(1000));
+    }
+
+    #[test]
+    fn rollover() {
+        let mut wheel: TimerWheel<usize, 8> = TimerWheel::new();
+        assert_eq!(None, wheel.dequeue_lt(usize::MAX - 10));
+        for (i, t) in [3, usize::MAX - 1, 0, usize::MAX - 3, 1 << 20]
+            .iter()
+            .enumerate()
+        {
+            assert!(wheel.try_enqueue(*t, i).is_ok());
+        }
+        assert_eq!(Some((usize::MAX - 3, 3)), wheel.dequeue_lt(usize::MAX - 2));
+        assert_eq!(None, wheel.dequeue_lt(usize::MAX - 2));
+        assert_eq!(Some((usize::MAX - 1, 1)), wheel.dequeue_lt(2));
+        assert_eq!(Some((0, 2)), wheel.dequeue_lt(2));
+        assert_eq!(None, wheel.dequeue_lt(2));
+        assert_eq!(Some((3, 0)), wheel.dequeue_lt(4));
+        assert_eq!(Some((1 << 20, 4)), wheel.dequeue_lt(1 << 21));
+    }
+
+    #[test]
+    fn cancel() {
+        let mut wheel: TimerWheel<usize, 16> = TimerWheel::new();
+        let mut handles = [None; 8];
+        for (i, handle) in handles.iter_mut().enumerate() {
+            *handle = wheel.try_enqueue_handle(i * 100, i).unwrap();
+        }
+        assert_eq!(Some((300, 3)), wheel.cancel(handles[3].unwrap()));
+        assert_eq!(None, wheel.cancel(handles[3].unwrap()));
+
+        let mut removed = 0;
+        assert_eq!(
+            3,
+            wheel.cancel_where(&mut |_, v| v % 2 == 1, &mut |_, _| removed += 1)
+        );
+        assert_eq!(3, removed);
+        assert_eq!(4, wheel.len());
+
+        //freed nodes are reused
+        assert!(wheel.try_enqueue(250, 100).is_ok());
+        for item in &[(0, 0), (200, 2), (250, 100), (400, 4), (600, 6)] {
+            assert_eq!(Some(*item), wheel.dequeue_lt(1000));
+        }
+        assert_eq!(None, wheel.dequeue_lt(1000));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn matches_binary_heap() {
+        use crate::pqueue::binaryheap::BinaryHeapQueue;
+
+        let mut wheel: std::boxed::Box<TimerWheel<usize, 512>> = std::boxed::Box::default();
+        let mut bheap: BinaryHeapQueue<usize> = BinaryHeapQueue::with_capacity(512);
+
+        //simple LCG so the sequence is repeatable
+        let mut seed = 1234usize;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+            seed
+        };
+        //start just before the rollover so that is covered too
+        let mut tick = usize::MAX - 5000;
+        for _ in 0..200 {
+            //a mix of near and far ticks, and some in the past
+            let offset = match next() % 5 {
+                0 => next() % 64,
+                1 => next() % 5000,
+                2 => next() % 1_000_000,
+                3 => next() << 12,
+                _ => 0,
+            };
+            let t = tick.wrapping_add(offset).wrapping_sub(next() % 8);
+            for _ in 0..(next() % 4) {
+                let v = next() % 4;
+                assert!(wheel.try_enqueue(t, v).is_ok());
+                assert!(bheap.try_enqueue(t, v).is_ok());
+            }
+            tick = tick.wrapping_add(next() % 2000);
+            loop {
+                let v = wheel.dequeue_lt(tick);
+                assert_eq!(bheap.dequeue_lt(tick), v);
+                if v.is_none() {
+                    break;
+                }
+            }
+        }
+        while !wheel.is_empty() {
+            tick = tick.wrapping_add(1 << 30);
+            loop {
+                let v = wheel.dequeue_lt(tick);
+                assert_eq!(bheap.dequeue_lt(tick), v);
+                if v.is_none() {
+                    break;
+                }
+            }
+        }
+        assert_eq!(None, bheap.dequeue_lt(tick));
+    }
+
+    #[test]
+    fn drops() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct DropCount;
+        impl Drop for DropCount {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, AOrdering::SeqCst);
+            }
+        }
+
+        {
+            let mut wheel: TimerWheel<DropCount, 8> = TimerWheel::new();
+            for i in 0..5 {
+                assert!(wheel.try_enqueue(i * 1000, DropCount).is_ok());
+            }
+            assert!(wheel.dequeue_lt(1).is_some());
+            assert_eq!(1, DROPS.load(AOrdering::SeqCst));
+        }
+        assert_eq!(5, DROPS.load(AOrdering::SeqCst));
+    }
+}
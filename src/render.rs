@@ -0,0 +1,388 @@
+//! Offline, faster than real-time, rendering.
+//!
+//! An `OfflineRender` drives a `SchedExec` for a given duration without an audio device and
+//! returns the events the graph emitted, with their absolute ticks. This is useful for tests and
+//! for exporting.
+extern crate alloc;
+
+use crate::{
+    event::EventEval,
+    pqueue::{TickHandle, TickPriorityDequeue, TickPriorityEnqueue},
+    sched::SchedExec,
+    spin::mutex::spin::SpinMutex,
+    Float,
+};
+use alloc::vec::Vec;
+
+/// A queue wrapper that moves the events `capture` selects into a log, instead of enqueuing
+/// them.
+///
+/// Events that are not captured, like graph roots rescheduling themselves, are enqueued into the
+/// wrapped `queue`, which the wrapper also dequeues from.
+pub struct CaptureEnqueue<Q, C, E> {
+    queue: Q,
+    capture: C,
+    log: Vec<(usize, E)>,
+}
+
+impl<Q, C, E> CaptureEnqueue<Q, C, E>
+where
+    Q: TickPriorityEnqueue<E> + TickPriorityDequeue<E>,
+    C: Fn(&E) -> bool + Send,
+    E: Send,
+{
+    pub fn new(queue: Q, capture: C) -> Self {
+        Self {
+            queue,
+            capture,
+            log: Vec::new(),
+        }
+    }
+
+    /// The captured events, in the order they were enqueued.
+    pub fn log(&self) -> &[(usize, E)] {
+        &self.log
+    }
+
+    /// Take the captured events, leaving the log empty.
+    pub fn take_log(&mut self) -> Vec<(usize, E)> {
+        core::mem::take(&mut self.log)
+    }
+
+    /// Enqueue directly into the wrapped queue, without capturing.
+    pub fn queue_mut(&mut self) -> &mut Q {
+        &mut self.queue
+    }
+}
+
+impl<Q, C, E> TickPriorityEnqueue<E> for CaptureEnqueue<Q, C, E>
+where
+    Q: TickPriorityEnqueue<E> + TickPriorityDequeue<E>,
+    C: Fn(&E) -> bool + Send,
+    E: Send,
+{
+    fn try_enqueue(&mut self, tick: usize, value: E) -> Result<(), E> {
+        if (self.capture)(&value) {
+            self.log.push((tick, value));
+            Ok(())
+        } else {
+            self.queue.try_enqueue(tick, value)
+        }
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: E) -> Result<Option<TickHandle>, E> {
+        if (self.capture)(&value) {
+            self.log.push((tick, value));
+            Ok(None)
+        } else {
+            self.queue.try_enqueue_handle(tick, value)
+        }
+    }
+}
+
+impl<Q, C, E> TickPriorityDequeue<E> for CaptureEnqueue<Q, C, E>
+where
+    Q: TickPriorityEnqueue<E> + TickPriorityDequeue<E>,
+    C: Fn(&E) -> bool + Send,
+    E: Send,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, E)> {
+        self.queue.dequeue_lt(tick)
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.queue.peek_lt(tick)
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        self.queue.len_hint()
+    }
+}
+
+/// Render a schedule offline, starting at tick 0.
+///
+/// Events scheduled while rendering, that `capture` returns true for, are collected instead of
+/// being evaluated.
+pub struct OfflineRender<Q, C, E> {
+    queue: SpinMutex<CaptureEnqueue<Q, C, E>>,
+    ticks_per_second: usize,
+}
+
+impl<Q, C, E> OfflineRender<Q, C, E>
+where
+    Q: TickPriorityEnqueue<E> + TickPriorityDequeue<E>,
+    C: Fn(&E) -> bool + Send,
+    E: EventEval<E>,
+{
+    pub fn new(queue: Q, capture: C, ticks_per_second: usize) -> Self {
+        Self {
+            queue: SpinMutex::new(CaptureEnqueue::new(queue, capture)),
+            ticks_per_second,
+        }
+    }
+
+    /// Schedule an event, usually a graph root, to be evaluated. These are never captured.
+    pub fn schedule(&mut self, tick: usize, event: E) -> Result<(), E> {
+        self.queue.get_mut().queue_mut().try_enqueue(tick, event)
+    }
+
+    /// Evaluate `ticks` ticks and return the captured events sorted by tick.
+    ///
+    /// Events captured at the same tick keep the order they were emitted in. Events emitted
+    /// during the render but scheduled after its end, like trailing note offs, are included.
+    pub fn render_ticks(self, ticks: usize) -> Vec<(usize, E)> {
+        let ticks_per_second = self.ticks_per_second;
+        {
+            let mut sched = SchedExec::new(&self.queue, &self.queue);
+            sched.run(ticks, ticks_per_second);
+        }
+        let mut log = self.queue.into_inner().take_log();
+        log.sort_by_key(|(tick, _)| *tick);
+        log
+    }
+
+    /// Evaluate `seconds` seconds, see `render_ticks`.
+    pub fn render_seconds(self, seconds: Float) -> Vec<(usize, E)> {
+        let ticks = num_traits::Float::round(seconds * self.ticks_per_second as Float) as usize;
+        self.render_ticks(ticks)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{ClockData, ClockPeriodGet},
+        event::{midi::MidiTryEnqueue, EventEvalContext},
+        graph::{
+            leaf::midi::MidiNote,
+            node::clock_ratio::ClockRatio,
+            root::{clock::RootClock, GraphRootWrapper},
+            GraphNodeWrapper,
+        },
+        position::{BarBeatTick, TimeSignature},
+        pqueue::binaryheap::BinaryHeapQueue,
+        tempo::{TempoMap, TempoRamp},
+        tick::{TickResched, TickSched},
+    };
+    use core::cmp::Ordering;
+
+    enum TestEvent {
+        Root(Box<dyn EventEval<TestEvent> + Sync>),
+        Note { on: bool, num: u8 },
+    }
+
+    impl TestEvent {
+        fn is_note(&self) -> bool {
+            matches!(self, Self::Note { .. })
+        }
+    }
+
+    impl EventEval<TestEvent> for TestEvent {
+        fn event_eval(&mut self, context: &mut dyn EventEvalContext<TestEvent>) -> TickResched {
+            match self {
+                Self::Root(r) => r.event_eval(context),
+                Self::Note { .. } => TickResched::None,
+            }
+        }
+    }
+
+    impl MidiTryEnqueue for TestEvent {
+        fn note_try_enqueue(
+            context: &mut dyn EventEvalContext<Self>,
+            time: TickSched,
+            on: bool,
+            _chan: u8,
+            num: u8,
+            _vel: u8,
+        ) -> Result<(), Self> {
+            context.event_try_schedule(time, Self::Note { on, num })
+        }
+    }
+
+    //only roots end up in the queue, order is irrelevant
+    impl Ord for TestEvent {
+        fn cmp(&self, _other: &Self) -> Ordering {
+            Ordering::Equal
+        }
+    }
+
+    impl PartialOrd for TestEvent {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl PartialEq for TestEvent {
+        fn eq(&self, _other: &Self) -> bool {
+            true
+        }
+    }
+
+    impl Eq for TestEvent {}
+
+    fn notes(log: &[(usize, TestEvent)]) -> Vec<(usize, bool, u8)> {
+        log.iter()
+            .map(|(t, e)| match e {
+                TestEvent::Note { on, num } => (*t, *on, *num),
+                TestEvent::Root(_) => panic!("roots should not be captured"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_clock() {
+        //a note every 10ms, one clock period long, at 1000 ticks per second
+        let note = GraphNodeWrapper::new(
+            MidiNote::new(60u8, 0u8, TickResched::ContextRelative(1), 127u8, 0u8),
+            (),
+        );
+        let clock = GraphRootWrapper::new(RootClock::new(10000f64, true, false), (note,));
+
+        let mut render =
+            OfflineRender::new(BinaryHeapQueue::with_capacity(16), TestEvent::is_note, 1000);
+        assert!(render.schedule(5, TestEvent::Root(Box::new(clock))).is_ok());
+
+        let log = render.render_seconds(0.03);
+        assert_eq!(
+            notes(&log),
+            vec![
+                (5, true, 60),
+                (15, false, 60),
+                (15, true, 60),
+                (25, false, 60),
+                (25, true, 60),
+                (35, false, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_polyrhythm() {
+        //7 notes every 5 clock ticks, the clock period is 2.5 ticks
+        let note = GraphNodeWrapper::new(
+            MidiNote::new(60u8, 0u8, TickResched::ContextRelative(1), 127u8, 0u8),
+            (),
+        );
+        let ratio = GraphNodeWrapper::new(ClockRatio::new(7usize, 5usize), (note,));
+        let clock = GraphRootWrapper::new(RootClock::new(2500f64, true, false), (ratio,));
+
+        let mut render =
+            OfflineRender::new(BinaryHeapQueue::with_capacity(16), TestEvent::is_note, 1000);
+        assert!(render.schedule(0, TestEvent::Root(Box::new(clock))).is_ok());
+
+        //every note is at the tick nearest to its exact time, without drift
+        let ons: Vec<usize> = notes(&render.render_ticks(10000))
+            .into_iter()
+            .filter_map(|(t, on, _)| if on { Some(t) } else { None })
+            .collect();
+        let expected: Vec<usize> = (0..5600)
+            .map(|k| num_traits::Float::round(k as f64 * 12.5 / 7.0) as usize)
+            .collect();
+        assert_eq!(expected, ons);
+    }
+
+    #[test]
+    fn render_short_period() {
+        //2.5 notes every tick, each at the tick nearest to its exact time
+        let note = GraphNodeWrapper::new(
+            MidiNote::new(60u8, 0u8, TickResched::ContextRelative(1), 127u8, 0u8),
+            (),
+        );
+        let clock = GraphRootWrapper::new(RootClock::new(400f64, true, false), (note,));
+
+        let mut render =
+            OfflineRender::new(BinaryHeapQueue::with_capacity(16), TestEvent::is_note, 1000);
+        assert!(render.schedule(0, TestEvent::Root(Box::new(clock))).is_ok());
+
+        let log = notes(&render.render_ticks(100));
+        let ons: Vec<usize> = log
+            .iter()
+            .filter_map(|(t, on, _)| if *on { Some(*t) } else { None })
+            .collect();
+        let expected: Vec<usize> = (0..250)
+            .map(|k| num_traits::Float::round(k as f64 * 0.4) as usize)
+            .collect();
+        assert_eq!(expected, ons);
+        //the first note starts and ends in the first tick, along with the start of the second
+        assert_eq!(3, log.iter().filter(|(t, _, _)| *t == 0).count());
+    }
+
+    #[test]
+    fn render_tempo_map() {
+        //a note every 16th, 120 bpm then 90 bpm from the third beat
+        let mut map: TempoMap<2> =
+            TempoMap::new(ClockData::new(120.0, 4), TimeSignature::default());
+        assert!(map.set_tempo(BarBeatTick::new(0, 2, 0), 90.0).is_ok());
+        let expected: Vec<usize> = (0..40)
+            .map(|k| num_traits::Float::round(map.tick_to_micros(k) / 1000.0) as usize)
+            .collect();
+
+        let note = GraphNodeWrapper::new(
+            MidiNote::new(60u8, 0u8, TickResched::ContextRelative(1), 127u8, 0u8),
+            (),
+        );
+        let clock = GraphRootWrapper::new(RootClock::new(map, true, false), (note,));
+
+        let mut render =
+            OfflineRender::new(BinaryHeapQueue::with_capacity(16), TestEvent::is_note, 1000);
+        assert!(render.schedule(0, TestEvent::Root(Box::new(clock))).is_ok());
+
+        let ons: Vec<usize> = notes(&render.render_ticks(expected[39] + 1))
+            .into_iter()
+            .filter_map(|(t, on, _)| if on { Some(t) } else { None })
+            .collect();
+        assert_eq!(1000, expected[8]);
+        assert_eq!(expected, ons);
+    }
+
+    #[test]
+    fn render_tempo_ramp() {
+        //a note every 16th, slowing from 120 to 60 bpm over the second bar
+        let mut map: TempoMap<2> =
+            TempoMap::new(ClockData::new(120.0, 4), TimeSignature::default());
+        assert!(map
+            .set_tempo_ramp(
+                BarBeatTick::new(1, 0, 0),
+                60.0,
+                BarBeatTick::new(1, 0, 0),
+                TempoRamp::Exponential
+            )
+            .is_ok());
+        let expected: Vec<usize> = (0..48)
+            .map(|k| num_traits::Float::round(map.tick_to_micros(k) / 1000.0) as usize)
+            .collect();
+
+        let note = GraphNodeWrapper::new(
+            MidiNote::new(60u8, 0u8, TickResched::ContextRelative(1), 127u8, 0u8),
+            (),
+        );
+        let clock = GraphRootWrapper::new(RootClock::new(map, true, false), (note,));
+
+        let mut render =
+            OfflineRender::new(BinaryHeapQueue::with_capacity(16), TestEvent::is_note, 1000);
+        assert!(render.schedule(0, TestEvent::Root(Box::new(clock))).is_ok());
+
+        let ons: Vec<usize> = notes(&render.render_ticks(expected[47] + 1))
+            .into_iter()
+            .filter_map(|(t, on, _)| if on { Some(t) } else { None })
+            .collect();
+        //the end of the ramp is exactly on the beat, 2s + 2s / ln(2)
+        assert_eq!(4885, expected[32]);
+        assert_eq!(expected, ons);
+    }
+
+    #[test]
+    fn capture_enqueue() {
+        let mut capture =
+            CaptureEnqueue::new(BinaryHeapQueue::with_capacity(4), |v: &usize| *v >= 10);
+        assert!(capture.try_enqueue(3, 1).is_ok());
+        assert!(capture.try_enqueue(2, 10).is_ok());
+        assert_eq!(Ok(None), capture.try_enqueue_handle(1, 11));
+        assert_eq!(capture.log(), &[(2, 10), (1, 11)]);
+        assert_eq!(Some((3, 1)), capture.dequeue_lt(4));
+        assert_eq!(None, capture.dequeue_lt(4));
+        assert_eq!(vec![(2, 10), (1, 11)], capture.take_log());
+        assert!(capture.log().is_empty());
+    }
+}
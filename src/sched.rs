@@ -1,18 +1,147 @@
 //! Schedules
 use crate::context::RootContext;
-use crate::event::*;
+use crate::event::{dispose::EventDispose, *};
 use crate::pqueue::{TickPriorityDequeue, TickPriorityEnqueue};
+use crate::stats::SchedStatsRecord;
 use crate::tick::*;
+use crate::transport::Transport;
+use core::ops::Range;
+
+/// What a `SchedExec` does with events that are dequeued after their tick has passed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum LatePolicy {
+    /// Evaluate late events at the start of the run.
+    #[default]
+    Play,
+    /// Dispose of events that are more than the given number of ticks late, without evaluating
+    /// them, evaluate the rest at the start of the run.
+    DropOlder(usize),
+}
+
+/// Told about every late event, before it is evaluated or dropped.
+pub trait LateHandler<E>: Send {
+    /// `event` was `ticks` ticks late, `dropped` is true if it will not be evaluated.
+    fn event_late(&mut self, ticks: usize, event: &E, dropped: bool);
+}
+
+/// Ignore late events.
+impl<E> LateHandler<E> for () {
+    fn event_late(&mut self, _ticks: usize, _event: &E, _dropped: bool) {}
+}
+
+impl<E, F> LateHandler<E> for F
+where
+    F: FnMut(usize, &E, bool) + Send,
+{
+    fn event_late(&mut self, ticks: usize, event: &E, dropped: bool) {
+        self(ticks, event, dropped)
+    }
+}
+
+/// Count late events, to detect an overloaded graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct LateCount {
+    /// The number of late events.
+    pub events: usize,
+    /// The number of those that were dropped.
+    pub dropped: usize,
+    /// The most ticks an event was late by.
+    pub max_ticks: usize,
+}
+
+impl<E> LateHandler<E> for LateCount {
+    fn event_late(&mut self, ticks: usize, _event: &E, dropped: bool) {
+        self.events += 1;
+        if dropped {
+            self.dropped += 1;
+        }
+        self.max_ticks = core::cmp::max(self.max_ticks, ticks);
+    }
+}
+
+/// Limits on the work a `SchedExec` does in a single run, `None` is unlimited.
+///
+/// When a limit is reached, the rest of the run's due events carry over to the next run, where
+/// they are late.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct RunBudget {
+    /// The most events to evaluate.
+    pub events: Option<usize>,
+    /// The most events to reschedule.
+    pub reschedules: Option<usize>,
+}
+
+impl RunBudget {
+    /// No limits.
+    pub const UNLIMITED: Self = Self {
+        events: None,
+        reschedules: None,
+    };
+
+    fn exhausted(&self, report: &RunReport) -> bool {
+        self.events.is_some_and(|max| report.evaluated >= max)
+            || self
+                .reschedules
+                .is_some_and(|max| report.rescheduled >= max)
+    }
+}
+
+/// What happened in a run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct RunReport {
+    /// The number of events evaluated.
+    pub evaluated: usize,
+    /// The number of events rescheduled.
+    pub rescheduled: usize,
+    /// The budget ran out, any events that were still due carry over to the next run.
+    pub overrun: bool,
+}
+
+//skipped entirely, unless the stats want it
+fn sample_queue_depth<S, R, E>(stats: &mut S, reader: &R)
+where
+    S: SchedStatsRecord,
+    R: TickPriorityDequeue<E>,
+{
+    if stats.wants_queue_depth() {
+        if let Some(depth) = reader.len_hint() {
+            stats.queue_depth(depth);
+        }
+    }
+}
 
 /// Schedule executor.
-pub struct SchedExec<R, W, E>
+///
+/// Events that are finished, or that cannot be rescheduled, are handed to `dispose`. The
+/// default, `()`, drops them in place.
+///
+/// Events dequeued after their tick has passed are handled according to the executor's
+/// `LatePolicy`, and reported to `late`.
+///
+/// A `RunBudget` can limit the work done in each run, so a misbehaving graph cannot stall the
+/// thread calling `run`.
+///
+/// What the executor does is reported to `stats`, the default, `()`, records nothing.
+///
+/// The executor owns a `Transport`, stopping it freezes evaluation, locating or looping it moves
+/// the transport position that roots see through their context.
+pub struct SchedExec<R, W, E, D = (), L = (), S = ()>
 where
     R: TickPriorityDequeue<E>,
     W: TickPriorityEnqueue<E>,
+    D: EventDispose<E>,
+    L: LateHandler<E>,
+    S: SchedStatsRecord,
 {
     tick_next: usize,
     schedule_reader: R,
     schedule_writer: W,
+    dispose: D,
+    late: L,
+    stats: S,
+    late_policy: LatePolicy,
+    budget: RunBudget,
+    transport: Transport,
     _phantom: core::marker::PhantomData<fn() -> E>,
 }
 
@@ -23,28 +152,167 @@ where
     E: EventEval<E>,
 {
     pub fn new(schedule_reader: R, schedule_writer: W) -> Self {
+        Self::with_dispose(schedule_reader, schedule_writer, ())
+    }
+}
+
+impl<R, W, E, D> SchedExec<R, W, E, D>
+where
+    R: TickPriorityDequeue<E>,
+    W: TickPriorityEnqueue<E>,
+    E: EventEval<E>,
+    D: EventDispose<E>,
+{
+    /// Create an executor that hands retired events to `dispose`.
+    pub fn with_dispose(schedule_reader: R, schedule_writer: W, dispose: D) -> Self {
+        Self::with_late(schedule_reader, schedule_writer, dispose, ())
+    }
+}
+
+impl<R, W, E, D, L> SchedExec<R, W, E, D, L>
+where
+    R: TickPriorityDequeue<E>,
+    W: TickPriorityEnqueue<E>,
+    E: EventEval<E>,
+    D: EventDispose<E>,
+    L: LateHandler<E>,
+{
+    /// Create an executor that hands retired events to `dispose` and reports late events to
+    /// `late`.
+    pub fn with_late(schedule_reader: R, schedule_writer: W, dispose: D, late: L) -> Self {
+        Self::with_stats(schedule_reader, schedule_writer, dispose, late, ())
+    }
+}
+
+impl<R, W, E, D, L, S> SchedExec<R, W, E, D, L, S>
+where
+    R: TickPriorityDequeue<E>,
+    W: TickPriorityEnqueue<E>,
+    E: EventEval<E>,
+    D: EventDispose<E>,
+    L: LateHandler<E>,
+    S: SchedStatsRecord,
+{
+    /// Create an executor that also records what it does to `stats`.
+    pub fn with_stats(
+        schedule_reader: R,
+        schedule_writer: W,
+        dispose: D,
+        late: L,
+        stats: S,
+    ) -> Self {
         Self {
             tick_next: 0usize,
             schedule_reader,
             schedule_writer,
+            dispose,
+            late,
+            stats,
+            late_policy: LatePolicy::default(),
+            budget: RunBudget::UNLIMITED,
+            transport: Transport::new(),
             _phantom: Default::default(),
         }
     }
 
-    pub fn run(&mut self, ticks: usize, ticks_per_second: usize) {
+    /// Evaluate all the events scheduled in the next `ticks` ticks.
+    pub fn run(&mut self, ticks: usize, ticks_per_second: usize) -> RunReport {
+        self.run_with(ticks, ticks_per_second, |_, _| ())
+    }
+
+    /// Evaluate all the events scheduled in the next `ticks` ticks, calling `func` with the
+    /// frame offset, within this run, of each event after it is evaluated.
+    ///
+    /// This lets an audio callback render events sample accurately, rather than at the start of
+    /// the block.
+    ///
+    /// If the transport is stopped nothing is evaluated and time does not advance. If it reaches
+    /// the end of its loop region, it is located back to the start of the region and evaluation
+    /// continues within the same run.
+    ///
+    /// Time advances by `ticks` even if the budget runs out.
+    pub fn run_with<F>(&mut self, ticks: usize, ticks_per_second: usize, mut func: F) -> RunReport
+    where
+        F: FnMut(usize, &mut E),
+    {
+        let mut report = RunReport::default();
+        if !self.transport.is_playing() {
+            return report;
+        }
+        self.stats.run_begin();
+        sample_queue_depth(&mut self.stats, &self.schedule_reader);
+
+        let mut remaining = ticks;
+        let mut frame = 0;
+        loop {
+            let (seg, looped) = match self.transport.ticks_until_loop_end(self.tick_next) {
+                Some(end) if end <= remaining => (end, true),
+                _ => (remaining, false),
+            };
+            self.run_segment(seg, ticks_per_second, frame, &mut func, &mut report);
+            if looped {
+                let start = self.transport.loop_region().map_or(0, |r| r.start);
+                self.transport.locate(self.tick_next, start);
+            }
+            remaining -= seg;
+            frame += seg;
+            if remaining == 0 {
+                break;
+            }
+        }
+        self.stats.run_end(&report);
+        report
+    }
+
+    fn run_segment<F>(
+        &mut self,
+        ticks: usize,
+        ticks_per_second: usize,
+        frame: usize,
+        func: &mut F,
+        report: &mut RunReport,
+    ) where
+        F: FnMut(usize, &mut E),
+    {
         let now = self.tick_next;
 
-        //Find the net run's tick and handle rollover
+        //ticks roll over, queues compare them with serial number arithmetic
         let next = now.wrapping_add(ticks);
-        let end = if next < now { core::usize::MAX } else { next };
-        //TODO there are likely other places where we have to deal with rollover
 
         let mut context = RootContext::new(now, ticks_per_second, &mut self.schedule_writer);
+        context.update_transport(self.transport.offset(), self.transport.epoch());
+        context.update_transport_sync_tick(next);
 
-        //evaluate events before next
-        while let Some((t, mut event)) = self.schedule_reader.dequeue_lt(end) {
-            //clamp below now, exal and dispose
-            let tick = if t < now { now } else { t };
+        //evaluate events before next, until the budget runs out
+        loop {
+            if self.budget.exhausted(report) {
+                //only an overrun if something due is left over
+                if self.schedule_reader.peek_lt(next).is_some() {
+                    report.overrun = true;
+                }
+                break;
+            }
+            let (t, mut event) = match self.schedule_reader.dequeue_lt(next) {
+                Some(item) => item,
+                None => break,
+            };
+            //late events are evaluated at now, or dropped, according to the policy
+            let tick = if tick_lt(t, now) {
+                let late = tick_diff(now, t) as usize;
+                let drop = match self.late_policy {
+                    LatePolicy::Play => false,
+                    LatePolicy::DropOlder(threshold) => late > threshold,
+                };
+                self.late.event_late(late, &event, drop);
+                self.stats.event_late(late);
+                if drop {
+                    let _ = self.dispose.try_dispose(event);
+                    continue;
+                }
+                now
+            } else {
+                t
+            };
             context.update_tick(tick);
 
             //eval and see about rescheduling
@@ -53,16 +321,94 @@ where
                 TickResched::ContextRelative(t) => Some(TickSched::ContextRelative(t as isize)),
                 TickResched::None => None,
             };
+            func(frame + tick_diff(tick, now) as usize, &mut event);
+            report.evaluated += 1;
 
-            //try to reschedule if we should
-            if let Some(t) = r {
-                let _ = context.event_try_schedule(t, event);
+            //try to reschedule if we should, otherwise dispose
+            let retired = match r {
+                Some(t) => {
+                    report.rescheduled += 1;
+                    context.event_try_schedule(t, event).err()
+                }
+                None => Some(event),
+            };
+            if let Some(event) = retired {
+                //if the dispose cannot take it, it is dropped here
+                let _ = self.dispose.try_dispose(event);
             }
+
+            sample_queue_depth(&mut self.stats, &self.schedule_reader);
+        }
+
+        let failures = context.enqueue_failures();
+        if failures > 0 {
+            self.stats.enqueue_failed(failures);
         }
 
         self.tick_next = next;
     }
 
+    pub fn budget(&self) -> RunBudget {
+        self.budget
+    }
+
+    /// Limit the work done in each run.
+    pub fn set_budget(&mut self, budget: RunBudget) {
+        self.budget = budget;
+    }
+
+    pub fn late_policy(&self) -> LatePolicy {
+        self.late_policy
+    }
+
+    pub fn set_late_policy(&mut self, policy: LatePolicy) {
+        self.late_policy = policy;
+    }
+
+    pub fn stats(&self) -> &S {
+        &self.stats
+    }
+
+    /// The handler that late events are reported to.
+    pub fn late(&self) -> &L {
+        &self.late
+    }
+
+    pub fn late_mut(&mut self) -> &mut L {
+        &mut self.late
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    pub fn play(&mut self) {
+        self.transport.play();
+    }
+
+    /// Stop evaluating events, `run` does nothing until `play` is called.
+    pub fn stop(&mut self) {
+        self.transport.stop();
+    }
+
+    /// Move the transport to `position`, starting at the next run.
+    ///
+    /// Scheduled events keep their ticks, roots resync to the new position the next time they
+    /// are evaluated.
+    pub fn locate(&mut self, position: usize) {
+        self.transport.locate(self.tick_next, position);
+    }
+
+    /// Loop the transport between the positions in `region`, or `None` to stop looping.
+    pub fn set_loop(&mut self, region: Option<Range<usize>>) {
+        self.transport.set_loop(region);
+    }
+
+    /// The transport position at the start of the next run.
+    pub fn position(&self) -> usize {
+        self.transport.position(self.tick_next)
+    }
+
     pub fn tick_next(&self) -> usize {
         self.tick_next
     }
@@ -72,9 +418,14 @@ where
 mod tests {
     use super::*;
     use crate::{
-        event::{boxed::EventContainer, EventEval, EventEvalContext},
+        event::{boxed::EventContainer, dispose::dispose_drain, EventEval, EventEvalContext},
         graph::root::{clock::RootClock, GraphRootWrapper},
-        pqueue::binaryheap::BinaryHeapQueue,
+        pqueue::{
+            binaryheap::BinaryHeapQueue,
+            spsc::{Queue, TickConsumer},
+            TickHandle, TickPriorityCancel,
+        },
+        spin::mutex::spin::SpinMutex,
     };
     use core::cmp::Ordering;
     use std::sync::{
@@ -84,22 +435,26 @@ mod tests {
 
     static ENUM_CNT: AtomicUsize = AtomicUsize::new(0);
     static REF_CNT: AtomicUsize = AtomicUsize::new(0);
+    static SPSC_CNT: AtomicUsize = AtomicUsize::new(0);
+    static WRAP_CNT: AtomicUsize = AtomicUsize::new(0);
+    static OFFSET_CNT: AtomicUsize = AtomicUsize::new(0);
+    static LATE_CNT: AtomicUsize = AtomicUsize::new(0);
 
     lazy_static::lazy_static! {
-        static ref CLOCK_REF: Mutex<GraphRootWrapper<RootClock<f64, bool, bool, RefEventContainer>, (), RefEventContainer>> = {
-            let c = Mutex::new(GraphRootWrapper::new(RootClock::new(1000f64, true, false), ()));
-            c
-        };
-        static ref CLOCK_ENUM: Mutex<GraphRootWrapper<RootClock<f64, bool, bool, EnumEvent>, (), EnumEvent>> = {
-            let c = Mutex::new(GraphRootWrapper::new(RootClock::new(1000f64, true, false), ()));
-            c
-        };
+        static ref WRAP_TICKS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    }
+
+    type TestClock<E> = GraphRootWrapper<RootClock<f64, bool, bool, E>, (), E>;
+
+    lazy_static::lazy_static! {
+        static ref CLOCK_REF: Mutex<TestClock<RefEventContainer>> =
+            Mutex::new(GraphRootWrapper::new(RootClock::new(1000f64, true, false), ()));
+        static ref CLOCK_ENUM: Mutex<TestClock<EnumEvent>> =
+            Mutex::new(GraphRootWrapper::new(RootClock::new(1000f64, true, false), ()));
     }
 
     enum EnumEvent {
-        Root(
-            &'static Mutex<GraphRootWrapper<RootClock<f64, bool, bool, EnumEvent>, (), EnumEvent>>,
-        ),
+        Root(&'static Mutex<TestClock<EnumEvent>>),
     }
 
     pub struct RefEventContainer {
@@ -107,7 +462,7 @@ mod tests {
     }
 
     pub fn ptr_cmp<T: ?Sized>(a: *const T, b: *const T) -> Ordering {
-        a.cmp(&b)
+        a.cast::<()>().cmp(&b.cast::<()>())
     }
 
     impl Ord for EnumEvent {
@@ -176,6 +531,30 @@ mod tests {
 
     impl Eq for RefEventContainer {}
 
+    struct CountEvent(&'static AtomicUsize);
+
+    struct RecordEvent(&'static Mutex<Vec<usize>>);
+
+    impl EventEval<EventContainer> for RecordEvent {
+        fn event_eval(
+            &mut self,
+            context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            self.0.lock().unwrap().push(context.tick_now());
+            TickResched::Relative(10)
+        }
+    }
+
+    impl EventEval<EventContainer> for CountEvent {
+        fn event_eval(
+            &mut self,
+            _context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            self.0.fetch_add(1, AOrdering::SeqCst);
+            TickResched::Relative(10)
+        }
+    }
+
     #[test]
     fn can_build_boxed() {
         let clock = GraphRootWrapper::new(RootClock::new(1.0 as crate::Float, true, false), ());
@@ -196,7 +575,7 @@ mod tests {
 
         ENUM_CNT.store(0, AOrdering::SeqCst);
 
-        assert!(reader.try_enqueue(0, EnumEvent::Root(&*CLOCK_ENUM)).is_ok());
+        assert!(reader.try_enqueue(0, EnumEvent::Root(&CLOCK_ENUM)).is_ok());
         let mut sched = SchedExec::new(reader, writer);
 
         sched.run(0, 44100);
@@ -222,4 +601,631 @@ mod tests {
         sched.run(1, 4410);
         assert_eq!(REF_CNT.load(AOrdering::SeqCst), 1);
     }
+
+    #[test]
+    fn spsc_reader_writer() {
+        let mut queue: Queue<(usize, EventContainer), 4> = Queue::new();
+        let (mut producer, consumer) = queue.split();
+        let consumer = SpinMutex::new(TickConsumer::new(
+            consumer,
+            BinaryHeapQueue::with_capacity(16),
+        ));
+        let mut sched = SchedExec::new(&consumer, &consumer);
+
+        //enqueue from another thread
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                assert!(producer
+                    .try_enqueue(5, EventContainer::new(Box::new(CountEvent(&SPSC_CNT))))
+                    .is_ok());
+            });
+        });
+
+        sched.run(5, 44100);
+        assert_eq!(SPSC_CNT.load(AOrdering::SeqCst), 0);
+        sched.run(1, 44100);
+        assert_eq!(SPSC_CNT.load(AOrdering::SeqCst), 1);
+        //rescheduled events are read back
+        sched.run(10, 44100);
+        assert_eq!(SPSC_CNT.load(AOrdering::SeqCst), 2);
+        sched.run(100, 44100);
+        assert_eq!(SPSC_CNT.load(AOrdering::SeqCst), 12);
+    }
+
+    #[test]
+    fn rollover() {
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::new(&queue, &queue);
+        sched.tick_next = usize::MAX - 20;
+
+        assert!(queue
+            .lock()
+            .try_enqueue(
+                usize::MAX - 15,
+                EventContainer::new(Box::new(RecordEvent(&WRAP_TICKS)))
+            )
+            .is_ok());
+        //an event from before now is clamped to now
+        assert!(queue
+            .lock()
+            .try_enqueue(
+                usize::MAX - 30,
+                EventContainer::new(Box::new(CountEvent(&WRAP_CNT)))
+            )
+            .is_ok());
+
+        for _ in 0..8 {
+            sched.run(8, 44100);
+        }
+        assert_eq!(sched.tick_next(), 43);
+        assert_eq!(
+            *WRAP_TICKS.lock().unwrap(),
+            vec![usize::MAX - 15, usize::MAX - 5, 4, 14, 24, 34]
+        );
+        //the count event was clamped to MAX - 20 and then runs every 10 ticks
+        assert_eq!(WRAP_CNT.load(AOrdering::SeqCst), 7);
+    }
+
+    #[test]
+    fn run_with_offsets() {
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::new(&queue, &queue);
+        let mut offsets = Vec::new();
+
+        assert!(queue
+            .lock()
+            .try_enqueue(3, EventContainer::new(Box::new(CountEvent(&OFFSET_CNT))))
+            .is_ok());
+
+        sched.run_with(16, 44100, |offset, _| offsets.push(offset));
+        assert_eq!(offsets, vec![3, 13]);
+
+        offsets.clear();
+        sched.run_with(16, 44100, |offset, _| offsets.push(offset));
+        assert_eq!(offsets, vec![7]);
+
+        offsets.clear();
+        sched.run_with(1, 44100, |offset, _| offsets.push(offset));
+        assert!(offsets.is_empty());
+        assert_eq!(OFFSET_CNT.load(AOrdering::SeqCst), 3);
+    }
+
+    #[test]
+    fn dispose() {
+        static DROP_CNT: AtomicUsize = AtomicUsize::new(0);
+
+        struct OnceEvent;
+
+        impl EventEval<EventContainer> for OnceEvent {
+            fn event_eval(
+                &mut self,
+                _context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                TickResched::None
+            }
+        }
+
+        impl Drop for OnceEvent {
+            fn drop(&mut self) {
+                DROP_CNT.fetch_add(1, AOrdering::SeqCst);
+            }
+        }
+
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut dispose: Queue<EventContainer, 4> = Queue::new();
+        let (dispose_producer, mut dispose_consumer) = dispose.split();
+        let mut sched = SchedExec::with_dispose(&queue, &queue, dispose_producer);
+
+        for t in 0..3 {
+            assert!(queue
+                .lock()
+                .try_enqueue(t, EventContainer::new(Box::new(OnceEvent)))
+                .is_ok());
+        }
+        sched.run(16, 44100);
+        assert_eq!(DROP_CNT.load(AOrdering::SeqCst), 0);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(3, dispose_drain(&mut dispose_consumer));
+            });
+        });
+        assert_eq!(DROP_CNT.load(AOrdering::SeqCst), 3);
+    }
+
+    #[test]
+    fn same_tick_priority() {
+        lazy_static::lazy_static! {
+            static ref ORDER: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+        }
+
+        struct NamedEvent(&'static str);
+
+        impl EventEval<EventContainer> for NamedEvent {
+            fn event_eval(
+                &mut self,
+                _context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                ORDER.lock().unwrap().push(self.0);
+                TickResched::None
+            }
+        }
+
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::new(&queue, &queue);
+        for (name, priority) in &[
+            ("on0", 0),
+            ("off0", -1),
+            ("on1", 0),
+            ("off1", -1),
+            ("on2", 0),
+        ] {
+            assert!(queue
+                .lock()
+                .try_enqueue(
+                    4,
+                    EventContainer::with_priority(Box::new(NamedEvent(name)), *priority)
+                )
+                .is_ok());
+        }
+        sched.run(8, 44100);
+        assert_eq!(
+            *ORDER.lock().unwrap(),
+            vec!["off0", "off1", "on0", "on1", "on2"]
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        lazy_static::lazy_static! {
+            static ref NOTES: Mutex<Vec<(usize, &'static str)>> = Mutex::new(Vec::new());
+            static ref HANDLE: Mutex<Option<TickHandle>> = Mutex::new(None);
+        }
+
+        struct NoteEvent(&'static str);
+
+        impl EventEval<EventContainer> for NoteEvent {
+            fn event_eval(
+                &mut self,
+                context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                NOTES.lock().unwrap().push((context.tick_now(), self.0));
+                TickResched::None
+            }
+        }
+
+        //schedules a note and keeps its handle
+        struct PatternEvent;
+
+        impl EventEval<EventContainer> for PatternEvent {
+            fn event_eval(
+                &mut self,
+                context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                let handle = context
+                    .event_try_schedule_handle(
+                        TickSched::Relative(10),
+                        EventContainer::new(Box::new(NoteEvent("handle"))),
+                    )
+                    .ok()
+                    .flatten();
+                *HANDLE.lock().unwrap() = handle;
+                TickResched::None
+            }
+        }
+
+        const PATTERN: usize = 1;
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::new(&queue, &queue);
+
+        for t in &[4, 12, 20] {
+            let mut off = EventContainer::with_priority(Box::new(NoteEvent("off")), -1);
+            let mut on = EventContainer::new(Box::new(NoteEvent("on")));
+            off.set_tag(PATTERN);
+            on.set_tag(PATTERN);
+            assert!(queue.lock().try_enqueue(*t + 2, off).is_ok());
+            assert!(queue.lock().try_enqueue(*t, on).is_ok());
+        }
+        assert!(queue
+            .lock()
+            .try_enqueue(0, EventContainer::new(Box::new(PatternEvent)))
+            .is_ok());
+
+        sched.run(8, 44100);
+        assert_eq!(*NOTES.lock().unwrap(), vec![(4, "on"), (6, "off")]);
+
+        //stop the pattern, pending note ons vanish but the note offs stay
+        let mut removed = 0;
+        assert_eq!(
+            2,
+            (&queue).cancel_where(
+                &mut |_, e| e.tag() == PATTERN && e.priority() >= 0,
+                &mut |_, _| removed += 1
+            )
+        );
+        assert_eq!(2, removed);
+
+        let handle = HANDLE
+            .lock()
+            .unwrap()
+            .expect("queue should provide handles");
+        assert!((&queue).cancel(handle).is_some());
+
+        sched.run(32, 44100);
+        assert_eq!(
+            *NOTES.lock().unwrap(),
+            vec![(4, "on"), (6, "off"), (14, "off"), (22, "off")]
+        );
+    }
+
+    type TransportLog = Mutex<Vec<(usize, usize)>>;
+
+    //a clock, 10 base ticks per context tick at 1000 ticks per second, that records the
+    //transport tick and context tick of each of its evaluations
+    fn transport_clock(log: &'static TransportLog) -> EventContainer {
+        use crate::graph::{func::LeafFunc, GraphLeafWrapper};
+
+        let leaf = GraphLeafWrapper::new(LeafFunc::new(
+            move |context: &mut dyn EventEvalContext<EventContainer>| {
+                log.lock()
+                    .unwrap()
+                    .push((context.transport_tick(), context.context_tick_now()));
+            },
+        ));
+        let clock = GraphRootWrapper::new(RootClock::new(10000f64, true, false), (leaf,));
+        EventContainer::new(Box::new(clock))
+    }
+
+    #[test]
+    fn transport_stop() {
+        lazy_static::lazy_static! {
+            static ref LOG: TransportLog = Mutex::new(Vec::new());
+        }
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        assert!(queue.lock().try_enqueue(0, transport_clock(&LOG)).is_ok());
+        let mut sched = SchedExec::new(&queue, &queue);
+
+        sched.stop();
+        assert!(!sched.transport().is_playing());
+        sched.run(50, 1000);
+        assert_eq!(0, sched.tick_next());
+        assert!(LOG.lock().unwrap().is_empty());
+
+        sched.play();
+        sched.run(25, 1000);
+        assert_eq!(25, sched.tick_next());
+        assert_eq!(25, sched.position());
+        assert_eq!(*LOG.lock().unwrap(), vec![(0, 0), (10, 1), (20, 2)]);
+    }
+
+    #[test]
+    fn transport_locate() {
+        lazy_static::lazy_static! {
+            static ref LOG: TransportLog = Mutex::new(Vec::new());
+        }
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        assert!(queue.lock().try_enqueue(0, transport_clock(&LOG)).is_ok());
+        let mut sched = SchedExec::new(&queue, &queue);
+
+        sched.run(35, 1000);
+        assert_eq!(
+            *LOG.lock().unwrap(),
+            vec![(0, 0), (10, 1), (20, 2), (30, 3)]
+        );
+        LOG.lock().unwrap().clear();
+
+        //between clock ticks, the clock waits for the next whole period
+        sched.locate(52);
+        assert_eq!(52, sched.position());
+        sched.run(20, 1000);
+        assert_eq!(*LOG.lock().unwrap(), vec![(60, 6), (70, 7)]);
+        assert_eq!(72, sched.position());
+        LOG.lock().unwrap().clear();
+
+        //onto a clock tick, the clock runs at the tick it was located at
+        sched.locate(90);
+        sched.run(15, 1000);
+        assert_eq!(*LOG.lock().unwrap(), vec![(90, 9), (100, 10)]);
+    }
+
+    #[test]
+    fn transport_loop() {
+        lazy_static::lazy_static! {
+            static ref LOG: TransportLog = Mutex::new(Vec::new());
+        }
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        assert!(queue.lock().try_enqueue(0, transport_clock(&LOG)).is_ok());
+        let mut sched = SchedExec::new(&queue, &queue);
+        let mut offsets = Vec::new();
+
+        sched.set_loop(Some(0..30));
+        sched.run_with(70, 1000, |offset, _| offsets.push(offset));
+        assert_eq!(
+            LOG.lock()
+                .unwrap()
+                .iter()
+                .map(|(_, c)| *c)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 0, 1, 2, 0]
+        );
+        //frame offsets are relative to the whole run
+        assert_eq!(offsets, vec![0, 10, 20, 30, 40, 50, 60]);
+        assert_eq!(70, sched.tick_next());
+        assert_eq!(10, sched.position());
+
+        //empty regions do not loop
+        sched.set_loop(Some(5..5));
+        assert_eq!(None, sched.transport().loop_region());
+    }
+
+    #[test]
+    fn transport_loop_between_ticks() {
+        lazy_static::lazy_static! {
+            static ref LOG: TransportLog = Mutex::new(Vec::new());
+        }
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        assert!(queue.lock().try_enqueue(0, transport_clock(&LOG)).is_ok());
+        let mut sched = SchedExec::new(&queue, &queue);
+
+        //the loop ends between clock ticks, the clock still starts over at the loop start
+        sched.set_loop(Some(0..25));
+        sched.run(60, 1000);
+        assert_eq!(
+            *LOG.lock().unwrap(),
+            vec![(0, 0), (10, 1), (20, 2), (0, 0), (10, 1), (20, 2), (0, 0),]
+        );
+        assert_eq!(10, sched.position());
+    }
+
+    #[test]
+    fn context_tick_offset() {
+        use crate::graph::{
+            func::LeafFunc,
+            node::{clock_ratio::ClockRatio, tick_offset::TickOffset},
+            GraphLeafWrapper, GraphNode, GraphNodeWrapper,
+        };
+        use crate::tick::TickSched;
+
+        type OffsetLog = Mutex<Vec<(usize, usize, usize, usize)>>;
+        lazy_static::lazy_static! {
+            static ref LOG: OffsetLog = Mutex::new(Vec::new());
+        }
+
+        //records the tick, context tick and the tick of context tick 0
+        fn leaf(id: usize) -> impl GraphNode<RefEventContainer> {
+            GraphLeafWrapper::new(LeafFunc::new(
+                move |context: &mut dyn EventEvalContext<RefEventContainer>| {
+                    let zero = TickSched::ContextAbsolute(0).to_absolute(context.as_tick_context());
+                    LOG.lock().unwrap().push((
+                        id,
+                        context.tick_now(),
+                        context.context_tick_now(),
+                        zero,
+                    ));
+                },
+            ))
+        }
+
+        let ratio = GraphNodeWrapper::new(ClockRatio::new(2usize, 1usize), (leaf(1),));
+        let offset = GraphNodeWrapper::new(TickOffset::new(2isize), (leaf(2),));
+        let clock = GraphRootWrapper::new(
+            RootClock::new(10000f64, true, false),
+            (leaf(0), ratio, offset),
+        );
+
+        //the graph nodes need a Sync event
+        let clock: &'static Mutex<dyn EventEval<RefEventContainer>> =
+            Box::leak(Box::new(Mutex::new(clock)));
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        assert!(queue
+            .lock()
+            .try_enqueue(30, RefEventContainer::new(clock))
+            .is_ok());
+        let mut sched = SchedExec::new(&queue, &queue);
+        sched.run(50, 1000);
+        assert_eq!(
+            *LOG.lock().unwrap(),
+            vec![
+                (0, 30, 0, 30),
+                (1, 30, 0, 30),
+                (1, 35, 1, 30),
+                (2, 30, 2, 10),
+                (0, 40, 1, 30),
+                (1, 40, 2, 30),
+                (1, 45, 3, 30),
+                (2, 40, 3, 10),
+            ]
+        );
+        LOG.lock().unwrap().clear();
+
+        //after a locate tick 0 is the start of the transport
+        sched.locate(25);
+        sched.run(10, 1000);
+        assert_eq!(
+            *LOG.lock().unwrap(),
+            vec![
+                (0, 55, 3, 25),
+                (1, 55, 6, 25),
+                (1, 60, 7, 25),
+                (2, 55, 5, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn late_policy() {
+        lazy_static::lazy_static! {
+            static ref TICKS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        }
+
+        struct OnceEvent;
+
+        impl EventEval<EventContainer> for OnceEvent {
+            fn event_eval(
+                &mut self,
+                context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                TICKS.lock().unwrap().push(context.tick_now());
+                TickResched::None
+            }
+        }
+
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::with_late(&queue, &queue, (), LateCount::default());
+        assert_eq!(LatePolicy::Play, sched.late_policy());
+        sched.run(10, 44100);
+
+        //played late, at the start of the run
+        for t in &[8, 1] {
+            assert!(queue
+                .lock()
+                .try_enqueue(*t, EventContainer::new(Box::new(OnceEvent)))
+                .is_ok());
+        }
+        sched.run(10, 44100);
+        assert_eq!(*TICKS.lock().unwrap(), vec![10, 10]);
+        assert_eq!(
+            LateCount {
+                events: 2,
+                dropped: 0,
+                max_ticks: 9
+            },
+            *sched.late()
+        );
+
+        //only the events that are too late are dropped
+        *sched.late_mut() = LateCount::default();
+        sched.set_late_policy(LatePolicy::DropOlder(5));
+        for t in &[17, 4, 22] {
+            assert!(queue
+                .lock()
+                .try_enqueue(*t, EventContainer::new(Box::new(OnceEvent)))
+                .is_ok());
+        }
+        sched.run(10, 44100);
+        assert_eq!(*TICKS.lock().unwrap(), vec![10, 10, 20, 22]);
+        assert_eq!(
+            LateCount {
+                events: 2,
+                dropped: 1,
+                max_ticks: 16
+            },
+            *sched.late()
+        );
+    }
+
+    #[test]
+    fn late_handler() {
+        let mut reports = Vec::new();
+        {
+            let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+            let mut sched = SchedExec::with_late(
+                &queue,
+                &queue,
+                (),
+                |ticks, _event: &EventContainer, dropped| reports.push((ticks, dropped)),
+            );
+            sched.set_late_policy(LatePolicy::DropOlder(3));
+            sched.run(10, 44100);
+            for t in &[9, 7, 6] {
+                assert!(queue
+                    .lock()
+                    .try_enqueue(*t, EventContainer::new(Box::new(CountEvent(&LATE_CNT))))
+                    .is_ok());
+            }
+            sched.run(1, 44100);
+        }
+        assert_eq!(reports, vec![(4, true), (3, false), (1, false)]);
+        assert_eq!(LATE_CNT.load(AOrdering::SeqCst), 2);
+    }
+
+    #[test]
+    fn budget() {
+        static SPIN_CNT: AtomicUsize = AtomicUsize::new(0);
+        static BUDGET_CNT: AtomicUsize = AtomicUsize::new(0);
+
+        //never advances, without a budget run would never return
+        struct SpinEvent;
+
+        impl EventEval<EventContainer> for SpinEvent {
+            fn event_eval(
+                &mut self,
+                _context: &mut dyn EventEvalContext<EventContainer>,
+            ) -> TickResched {
+                SPIN_CNT.fetch_add(1, AOrdering::SeqCst);
+                TickResched::Relative(0)
+            }
+        }
+
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(16));
+        let mut sched = SchedExec::new(&queue, &queue);
+        assert_eq!(RunBudget::UNLIMITED, sched.budget());
+        sched.set_budget(RunBudget {
+            events: Some(8),
+            reschedules: None,
+        });
+        assert!(queue
+            .lock()
+            .try_enqueue(3, EventContainer::new(Box::new(SpinEvent)))
+            .is_ok());
+
+        assert_eq!(
+            RunReport {
+                evaluated: 8,
+                rescheduled: 8,
+                overrun: true
+            },
+            sched.run(10, 44100)
+        );
+        assert_eq!(10, sched.tick_next());
+
+        //the event carries over to the next run
+        sched.set_budget(RunBudget {
+            events: None,
+            reschedules: Some(3),
+        });
+        assert_eq!(
+            RunReport {
+                evaluated: 3,
+                rescheduled: 3,
+                overrun: true
+            },
+            sched.run(10, 44100)
+        );
+        assert_eq!(11, SPIN_CNT.load(AOrdering::SeqCst));
+
+        //a schedule within budget doesn't overrun
+        assert_eq!(1, (&queue).cancel_where(&mut |_, _| true, &mut |_, _| ()));
+        for t in &[25, 27] {
+            assert!(queue
+                .lock()
+                .try_enqueue(*t, EventContainer::new(Box::new(CountEvent(&BUDGET_CNT))))
+                .is_ok());
+        }
+        assert_eq!(
+            RunReport {
+                evaluated: 2,
+                rescheduled: 2,
+                overrun: false
+            },
+            sched.run(10, 44100)
+        );
+        assert_eq!(11, SPIN_CNT.load(AOrdering::SeqCst));
+        assert_eq!(2, BUDGET_CNT.load(AOrdering::SeqCst));
+
+        //using up exactly the budget doesn't overrun either
+        sched.set_budget(RunBudget {
+            events: Some(2),
+            reschedules: None,
+        });
+        assert_eq!(
+            RunReport {
+                evaluated: 2,
+                rescheduled: 2,
+                overrun: false
+            },
+            sched.run(10, 44100)
+        );
+        assert_eq!(4, BUDGET_CNT.load(AOrdering::SeqCst));
+    }
 }
@@ -0,0 +1,526 @@
+//! Scheduler statistics.
+//!
+//! A `SchedExec` reports what it does to a `SchedStatsRecord`. The default, `()`, ignores
+//! everything, so the calls compile away. `&SchedStats` records into atomics that can be read
+//! from another thread without locking, for instance from a UI showing the load of the audio
+//! thread.
+//!
+//! Queues are measured by wrapping them in a `StatsQueue`, which reports to a `QueueStatsRecord`
+//! the same way, `()` or `&QueueStats`.
+use crate::{
+    pqueue::{TickHandle, TickPriorityCancel, TickPriorityDequeue, TickPriorityEnqueue},
+    sched::RunReport,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub trait SchedStatsRecord: Send {
+    /// Called at the start of each run, unless the transport is stopped.
+    fn run_begin(&mut self) {}
+
+    /// Called at the end of each run with what happened.
+    fn run_end(&mut self, _report: &RunReport) {}
+
+    /// `count` events could not be scheduled.
+    fn enqueue_failed(&mut self, _count: usize) {}
+
+    /// An event was evaluated `ticks` ticks late, or dropped.
+    fn event_late(&mut self, _ticks: usize) {}
+
+    /// Return true to have `queue_depth` called, sampling the depth can mean locking the queue.
+    fn wants_queue_depth(&self) -> bool {
+        false
+    }
+
+    /// The number of items in the schedule queue, sampled at the start of each run and after
+    /// every evaluation.
+    fn queue_depth(&mut self, _depth: usize) {}
+}
+
+/// Record nothing.
+impl SchedStatsRecord for () {}
+
+pub trait QueueStatsRecord: Send {
+    /// An item was enqueued.
+    fn enqueued(&mut self) {}
+
+    /// An item could not be enqueued, the queue was full.
+    fn enqueue_failed(&mut self) {}
+
+    /// `count` items were cancelled.
+    fn cancelled(&mut self, _count: usize) {}
+
+    /// Return true to have `len` called, sampling the length can mean locking the queue.
+    fn wants_len(&self) -> bool {
+        false
+    }
+
+    /// The number of items in the queue, sampled before every dequeue.
+    fn len(&mut self, _len: usize) {}
+}
+
+/// Record nothing.
+impl QueueStatsRecord for () {}
+
+/// A queue that reports what happens to it to a `QueueStatsRecord`.
+///
+/// The length is only sampled before dequeuing, between dequeues a queue only grows, unless
+/// items are cancelled, so this sees the peak length of queues that are dequeued regularly.
+pub struct StatsQueue<Q, S = ()> {
+    queue: Q,
+    stats: S,
+}
+
+/// Statistics that can be shared between the scheduler thread and readers.
+///
+/// All the values are updated with relaxed atomics, a reader may see the values of a run
+/// partially updated. Run times are only measured with the `std` feature, on 32 bit targets runs
+/// longer than about 4.3 seconds wrap.
+pub struct SchedStats {
+    runs: AtomicUsize,
+    evaluated: AtomicUsize,
+    evaluated_last: AtomicUsize,
+    evaluated_max: AtomicUsize,
+    overruns: AtomicUsize,
+    enqueue_failures: AtomicUsize,
+    queue_depth_peak: AtomicUsize,
+    late: AtomicUsize,
+    late_max_ticks: AtomicUsize,
+    run_nanos_last: AtomicUsize,
+    run_nanos_max: AtomicUsize,
+    #[cfg(feature = "std")]
+    run_start: AtomicUsize,
+    #[cfg(feature = "std")]
+    epoch: std::time::Instant,
+}
+
+/// A copy of the values in `SchedStats`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct SchedStatsSnapshot {
+    /// The number of runs.
+    pub runs: usize,
+    /// The total number of events evaluated.
+    pub evaluated: usize,
+    /// The number of events evaluated in the last run.
+    pub evaluated_last: usize,
+    /// The most events evaluated in a run.
+    pub evaluated_max: usize,
+    /// The number of runs that ran out of budget.
+    pub overruns: usize,
+    /// The number of events that could not be scheduled.
+    pub enqueue_failures: usize,
+    /// The most items seen in the schedule queue.
+    pub queue_depth_peak: usize,
+    /// The number of late events.
+    pub late: usize,
+    /// The most ticks an event was late by.
+    pub late_max_ticks: usize,
+    /// The duration of the last run.
+    pub run_nanos_last: usize,
+    /// The longest run.
+    pub run_nanos_max: usize,
+}
+
+/// Queue statistics that can be shared between threads, see `SchedStats`.
+pub struct QueueStats {
+    enqueued: AtomicUsize,
+    enqueue_failures: AtomicUsize,
+    cancelled: AtomicUsize,
+    len_peak: AtomicUsize,
+}
+
+/// A copy of the values in `QueueStats`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct QueueStatsSnapshot {
+    /// The number of items enqueued.
+    pub enqueued: usize,
+    /// The number of items that could not be enqueued.
+    pub enqueue_failures: usize,
+    /// The number of items cancelled.
+    pub cancelled: usize,
+    /// The most items seen in the queue.
+    pub len_peak: usize,
+}
+
+impl SchedStats {
+    pub fn new() -> Self {
+        Self {
+            runs: AtomicUsize::new(0),
+            evaluated: AtomicUsize::new(0),
+            evaluated_last: AtomicUsize::new(0),
+            evaluated_max: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            enqueue_failures: AtomicUsize::new(0),
+            queue_depth_peak: AtomicUsize::new(0),
+            late: AtomicUsize::new(0),
+            late_max_ticks: AtomicUsize::new(0),
+            run_nanos_last: AtomicUsize::new(0),
+            run_nanos_max: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            run_start: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            epoch: std::time::Instant::now(),
+        }
+    }
+
+    /// Read all the values.
+    pub fn snapshot(&self) -> SchedStatsSnapshot {
+        SchedStatsSnapshot {
+            runs: self.runs.load(Ordering::Relaxed),
+            evaluated: self.evaluated.load(Ordering::Relaxed),
+            evaluated_last: self.evaluated_last.load(Ordering::Relaxed),
+            evaluated_max: self.evaluated_max.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            enqueue_failures: self.enqueue_failures.load(Ordering::Relaxed),
+            queue_depth_peak: self.queue_depth_peak.load(Ordering::Relaxed),
+            late: self.late.load(Ordering::Relaxed),
+            late_max_ticks: self.late_max_ticks.load(Ordering::Relaxed),
+            run_nanos_last: self.run_nanos_last.load(Ordering::Relaxed),
+            run_nanos_max: self.run_nanos_max.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zero all the values.
+    pub fn reset(&self) {
+        for v in &[
+            &self.runs,
+            &self.evaluated,
+            &self.evaluated_last,
+            &self.evaluated_max,
+            &self.overruns,
+            &self.enqueue_failures,
+            &self.queue_depth_peak,
+            &self.late,
+            &self.late_max_ticks,
+            &self.run_nanos_last,
+            &self.run_nanos_max,
+        ] {
+            v.store(0, Ordering::Relaxed);
+        }
+    }
+
+    //wraps, after about 4.3 seconds on 32 bit targets, so durations are wrapping differences
+    #[cfg(feature = "std")]
+    fn nanos(&self) -> usize {
+        self.epoch.elapsed().as_nanos() as usize
+    }
+}
+
+impl Default for SchedStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedStatsRecord for &SchedStats {
+    fn run_begin(&mut self) {
+        #[cfg(feature = "std")]
+        self.run_start.store(self.nanos(), Ordering::Relaxed);
+    }
+
+    fn run_end(&mut self, report: &RunReport) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.evaluated
+            .fetch_add(report.evaluated, Ordering::Relaxed);
+        self.evaluated_last
+            .store(report.evaluated, Ordering::Relaxed);
+        self.evaluated_max
+            .fetch_max(report.evaluated, Ordering::Relaxed);
+        if report.overrun {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "std")]
+        {
+            let nanos = self
+                .nanos()
+                .wrapping_sub(self.run_start.load(Ordering::Relaxed));
+            self.run_nanos_last.store(nanos, Ordering::Relaxed);
+            self.run_nanos_max.fetch_max(nanos, Ordering::Relaxed);
+        }
+    }
+
+    fn enqueue_failed(&mut self, count: usize) {
+        self.enqueue_failures.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn event_late(&mut self, ticks: usize) {
+        self.late.fetch_add(1, Ordering::Relaxed);
+        self.late_max_ticks.fetch_max(ticks, Ordering::Relaxed);
+    }
+
+    fn wants_queue_depth(&self) -> bool {
+        true
+    }
+
+    fn queue_depth(&mut self, depth: usize) {
+        self.queue_depth_peak.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+impl QueueStats {
+    pub fn new() -> Self {
+        Self {
+            enqueued: AtomicUsize::new(0),
+            enqueue_failures: AtomicUsize::new(0),
+            cancelled: AtomicUsize::new(0),
+            len_peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// Read all the values.
+    pub fn snapshot(&self) -> QueueStatsSnapshot {
+        QueueStatsSnapshot {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            enqueue_failures: self.enqueue_failures.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            len_peak: self.len_peak.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zero all the values.
+    pub fn reset(&self) {
+        for v in &[
+            &self.enqueued,
+            &self.enqueue_failures,
+            &self.cancelled,
+            &self.len_peak,
+        ] {
+            v.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for QueueStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueueStatsRecord for &QueueStats {
+    fn enqueued(&mut self) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn enqueue_failed(&mut self) {
+        self.enqueue_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn cancelled(&mut self, count: usize) {
+        self.cancelled.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn wants_len(&self) -> bool {
+        true
+    }
+
+    fn len(&mut self, len: usize) {
+        self.len_peak.fetch_max(len, Ordering::Relaxed);
+    }
+}
+
+impl<Q, S> StatsQueue<Q, S>
+where
+    S: QueueStatsRecord,
+{
+    pub fn new(queue: Q, stats: S) -> Self {
+        Self { queue, stats }
+    }
+
+    pub fn inner(&self) -> &Q {
+        &self.queue
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Q {
+        &mut self.queue
+    }
+
+    pub fn into_inner(self) -> Q {
+        self.queue
+    }
+
+    fn record_enqueue<R, T>(&mut self, r: Result<R, T>) -> Result<R, T> {
+        if r.is_ok() {
+            self.stats.enqueued();
+        } else {
+            self.stats.enqueue_failed();
+        }
+        r
+    }
+}
+
+impl<T, Q, S> TickPriorityEnqueue<T> for StatsQueue<Q, S>
+where
+    Q: TickPriorityEnqueue<T>,
+    S: QueueStatsRecord,
+{
+    fn try_enqueue(&mut self, tick: usize, value: T) -> Result<(), T> {
+        let r = self.queue.try_enqueue(tick, value);
+        self.record_enqueue(r)
+    }
+
+    fn try_enqueue_handle(&mut self, tick: usize, value: T) -> Result<Option<TickHandle>, T> {
+        let r = self.queue.try_enqueue_handle(tick, value);
+        self.record_enqueue(r)
+    }
+}
+
+impl<T, Q, S> TickPriorityDequeue<T> for StatsQueue<Q, S>
+where
+    Q: TickPriorityDequeue<T>,
+    S: QueueStatsRecord,
+{
+    fn dequeue_lt(&mut self, tick: usize) -> Option<(usize, T)> {
+        if self.stats.wants_len() {
+            if let Some(len) = self.queue.len_hint() {
+                self.stats.len(len);
+            }
+        }
+        self.queue.dequeue_lt(tick)
+    }
+
+    fn peek_lt(&mut self, tick: usize) -> Option<usize> {
+        self.queue.peek_lt(tick)
+    }
+
+    fn len_hint(&self) -> Option<usize> {
+        self.queue.len_hint()
+    }
+}
+
+impl<T, Q, S> TickPriorityCancel<T> for StatsQueue<Q, S>
+where
+    Q: TickPriorityCancel<T>,
+    S: QueueStatsRecord,
+{
+    fn cancel(&mut self, handle: TickHandle) -> Option<(usize, T)> {
+        let r = self.queue.cancel(handle);
+        if r.is_some() {
+            self.stats.cancelled(1);
+        }
+        r
+    }
+
+    fn cancel_where(
+        &mut self,
+        pred: &mut dyn FnMut(usize, &T) -> bool,
+        removed: &mut dyn FnMut(usize, T),
+    ) -> usize {
+        let count = self.queue.cancel_where(pred, removed);
+        if count > 0 {
+            self.stats.cancelled(count);
+        }
+        count
+    }
+}
+
+#[cfg(all(test, feature = "with_alloc", feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        event::{boxed::EventContainer, EventEval, EventEvalContext},
+        pqueue::{binaryheap::BinaryHeapQueue, staticheap::StaticTickHeap},
+        sched::SchedExec,
+        spin::mutex::spin::SpinMutex,
+        tick::{TickResched, TickSched},
+    };
+
+    struct OnceEvent;
+
+    impl EventEval<EventContainer> for OnceEvent {
+        fn event_eval(
+            &mut self,
+            _context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            TickResched::None
+        }
+    }
+
+    //schedules more events than the queue can hold
+    struct SpawnEvent;
+
+    impl EventEval<EventContainer> for SpawnEvent {
+        fn event_eval(
+            &mut self,
+            context: &mut dyn EventEvalContext<EventContainer>,
+        ) -> TickResched {
+            for i in 1..=5 {
+                let _ = context.event_try_schedule(
+                    TickSched::Relative(i),
+                    EventContainer::new(Box::new(OnceEvent)),
+                );
+            }
+            TickResched::None
+        }
+    }
+
+    #[test]
+    fn record() {
+        let queue = SpinMutex::new(BinaryHeapQueue::with_capacity(4));
+        let stats = SchedStats::new();
+        let mut sched = SchedExec::with_stats(&queue, &queue, (), (), &stats);
+
+        assert!(queue
+            .lock()
+            .try_enqueue(2, EventContainer::new(Box::new(SpawnEvent)))
+            .is_ok());
+        sched.run(10, 44100);
+        assert!(queue
+            .lock()
+            .try_enqueue(5, EventContainer::new(Box::new(OnceEvent)))
+            .is_ok());
+        sched.run(10, 44100);
+
+        //read from another thread
+        let snapshot = std::thread::scope(|s| s.spawn(|| stats.snapshot()).join().unwrap());
+        assert_eq!(
+            SchedStatsSnapshot {
+                runs: 2,
+                evaluated: 6,
+                evaluated_last: 1,
+                evaluated_max: 5,
+                overruns: 0,
+                enqueue_failures: 1,
+                queue_depth_peak: 4,
+                late: 1,
+                late_max_ticks: 5,
+                run_nanos_last: snapshot.run_nanos_last,
+                run_nanos_max: snapshot.run_nanos_max,
+            },
+            snapshot
+        );
+        assert!(snapshot.run_nanos_max >= snapshot.run_nanos_last);
+
+        stats.reset();
+        assert_eq!(SchedStatsSnapshot::default(), stats.snapshot());
+    }
+
+    #[test]
+    fn record_queue() {
+        let stats = QueueStats::new();
+        let queue = SpinMutex::new(StatsQueue::new(StaticTickHeap::<usize, 4>::new(), &stats));
+        let mut handles = [None; 4];
+        for (i, h) in handles.iter_mut().enumerate() {
+            *h = (&queue).try_enqueue_handle(10 + i, i).unwrap();
+        }
+        assert_eq!(Err(4), (&queue).try_enqueue(20, 4));
+
+        assert_eq!(Some((10, 0)), (&queue).dequeue_lt(11));
+        assert_eq!(Some((11, 1)), (&queue).cancel(handles[1].unwrap()));
+        assert_eq!(None, (&queue).cancel(handles[1].unwrap()));
+        assert_eq!(2, (&queue).cancel_where(&mut |_, _| true, &mut |_, _| {}));
+        assert_eq!(None, (&queue).dequeue_lt(100));
+
+        let snapshot = std::thread::scope(|s| s.spawn(|| stats.snapshot()).join().unwrap());
+        assert_eq!(
+            QueueStatsSnapshot {
+                enqueued: 4,
+                enqueue_failures: 1,
+                cancelled: 3,
+                len_peak: 4,
+            },
+            snapshot
+        );
+
+        stats.reset();
+        assert_eq!(QueueStatsSnapshot::default(), stats.snapshot());
+    }
+}
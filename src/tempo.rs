@@ -0,0 +1,698 @@
+//! Tempo maps, tempo and time signature changes at musical positions.
+use crate::{
+    clock::{ClockData, ClockPeriodGet},
+    position::{BarBeatTick, TimeSignature},
+    spin::mutex::spin::SpinMutex,
+    Float,
+};
+
+/// The shape of a tempo ramp, in ticks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TempoRamp {
+    /// The bpm changes by the same amount every tick.
+    Linear,
+    /// The bpm changes by the same ratio every tick.
+    Exponential,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Tempo {
+    position: BarBeatTick,
+    clock: ClockData,
+    tick: usize,
+    micros: Float,
+    ramp: Option<(TempoRamp, BarBeatTick)>,
+    ramp_ticks: usize,
+    //the bpm at the start of the ramp
+    from_bpm: Float,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Meter {
+    bar: usize,
+    sig: TimeSignature,
+    tick: usize,
+}
+
+/// A map of up to `N` tempo changes and `N` time signature changes.
+///
+/// Ticks are the ticks of a clock with the map's `ppq`, starting from 0 at `0:0:0`. There is
+/// always a tempo and time signature at `0:0:0`, they can be replaced but not removed.
+///
+/// The map can be the period source of a `RootClock`, share a `&'static SpinMutex<TempoMap>` to
+/// edit it while the clock runs, keep the edits short, the clock holds its period until they
+/// are done.
+#[derive(Copy, Clone, Debug)]
+pub struct TempoMap<const N: usize> {
+    tempos: [Tempo; N],
+    tempos_len: usize,
+    meters: [Meter; N],
+    meters_len: usize,
+}
+
+impl<const N: usize> TempoMap<N> {
+    /// Create a map with the tempo and ppq of `clock` and the time signature `sig`.
+    pub const fn new(clock: ClockData, sig: TimeSignature) -> Self {
+        assert!(N > 0);
+        Self {
+            tempos: [Tempo {
+                position: BarBeatTick::new(0, 0, 0),
+                clock,
+                tick: 0,
+                micros: 0.0,
+                ramp: None,
+                ramp_ticks: 0,
+                from_bpm: clock.bpm,
+            }; N],
+            tempos_len: 1,
+            meters: [Meter {
+                bar: 0,
+                sig,
+                tick: 0,
+            }; N],
+            meters_len: 1,
+        }
+    }
+
+    pub fn ppq(&self) -> usize {
+        self.tempos[0].clock.ppq
+    }
+
+    /// Set the tempo, in beats per minute, from `position` on.
+    ///
+    /// Replaces the tempo at the same tick, returns the bpm back if the map is full.
+    pub fn set_tempo(&mut self, position: BarBeatTick, bpm: Float) -> Result<(), Float> {
+        self.insert_tempo(position, bpm, None)
+    }
+
+    /// Ramp the tempo, from the tempo in effect at `position`, to `bpm` over `length`.
+    ///
+    /// The tempo stays at `bpm` after the ramp, a later tempo change cuts the ramp short. A ramp
+    /// at `0:0:0` has no tempo to start from, so it is a plain tempo change.
+    pub fn set_tempo_ramp(
+        &mut self,
+        position: BarBeatTick,
+        bpm: Float,
+        length: BarBeatTick,
+        ramp: TempoRamp,
+    ) -> Result<(), Float> {
+        self.insert_tempo(position, bpm, Some((ramp, length)))
+    }
+
+    fn insert_tempo(
+        &mut self,
+        position: BarBeatTick,
+        bpm: Float,
+        ramp: Option<(TempoRamp, BarBeatTick)>,
+    ) -> Result<(), Float> {
+        let clock = ClockData::new(bpm, self.ppq());
+        let tick = self.position_to_ticks(position);
+        if let Some(t) = self.tempos[..self.tempos_len]
+            .iter_mut()
+            .find(|t| t.tick == tick)
+        {
+            t.position = position;
+            t.clock = clock;
+            t.ramp = ramp;
+        } else if self.tempos_len < N {
+            self.tempos[self.tempos_len] = Tempo {
+                position,
+                clock,
+                tick,
+                micros: 0.0,
+                ramp,
+                ramp_ticks: 0,
+                from_bpm: bpm,
+            };
+            self.tempos_len += 1;
+        } else {
+            return Err(bpm);
+        }
+        self.update();
+        Ok(())
+    }
+
+    /// Remove the tempo change at `position`, returns false if there is none or it is at tick 0.
+    pub fn remove_tempo(&mut self, position: BarBeatTick) -> bool {
+        let tick = self.position_to_ticks(position);
+        match self.tempos[1..self.tempos_len]
+            .iter()
+            .position(|t| t.tick == tick)
+        {
+            Some(index) => {
+                self.tempos
+                    .copy_within(index + 2..self.tempos_len, index + 1);
+                self.tempos_len -= 1;
+                self.update();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the time signature from the start of `bar` on.
+    ///
+    /// Replaces the signature at the same bar, returns the signature back if the map is full.
+    /// Tempo changes keep their musical positions.
+    pub fn set_signature(&mut self, bar: usize, sig: TimeSignature) -> Result<(), TimeSignature> {
+        if let Some(m) = self.meters[..self.meters_len]
+            .iter_mut()
+            .find(|m| m.bar == bar)
+        {
+            m.sig = sig;
+        } else if self.meters_len < N {
+            self.meters[self.meters_len] = Meter { bar, sig, tick: 0 };
+            self.meters_len += 1;
+        } else {
+            return Err(sig);
+        }
+        self.update();
+        Ok(())
+    }
+
+    /// Remove the time signature change at `bar`, returns false if there is none or it is at
+    /// bar 0.
+    pub fn remove_signature(&mut self, bar: usize) -> bool {
+        match self.meters[1..self.meters_len]
+            .iter()
+            .position(|m| m.bar == bar)
+        {
+            Some(index) => {
+                self.meters
+                    .copy_within(index + 2..self.meters_len, index + 1);
+                self.meters_len -= 1;
+                self.update();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The tempo, in beats per minute, at `tick`.
+    pub fn bpm_at(&self, tick: usize) -> Float {
+        let t = self.tempo_at(tick);
+        t.bpm((tick - t.tick) as Float)
+    }
+
+    /// The time signature in effect at `bar`.
+    pub fn signature_at(&self, bar: usize) -> TimeSignature {
+        self.meter_at_bar(bar).sig
+    }
+
+    /// The tick of `position`, beats and ticks past the end of a bar or beat carry over.
+    pub fn position_to_ticks(&self, position: BarBeatTick) -> usize {
+        let m = self.meter_at_bar(position.bar);
+        let clock = &self.tempos[0].clock;
+        m.tick
+            + BarBeatTick::new(position.bar - m.bar, position.beat, position.tick)
+                .to_ticks(m.sig, clock)
+    }
+
+    /// The musical position of `tick`.
+    pub fn ticks_to_position(&self, tick: usize) -> BarBeatTick {
+        let m = self.meters[..self.meters_len]
+            .iter()
+            .rev()
+            .find(|m| m.tick <= tick)
+            .unwrap_or(&self.meters[0]);
+        let mut position = BarBeatTick::from_ticks(tick - m.tick, m.sig, &self.tempos[0].clock);
+        position.bar += m.bar;
+        position
+    }
+
+    /// The time, in seconds, from the start to `ticks`.
+    pub fn ticks_to_seconds(&self, ticks: Float) -> Float {
+        self.ticks_to_micros(ticks) / 1e6
+    }
+
+    /// The ticks, with a fraction, from the start to `seconds`.
+    pub fn seconds_to_ticks(&self, seconds: Float) -> Float {
+        self.micros_to_ticks(seconds * 1e6)
+    }
+
+    /// The time, in seconds, from the start to `position`.
+    pub fn position_to_seconds(&self, position: BarBeatTick) -> Float {
+        self.ticks_to_seconds(self.position_to_ticks(position) as Float)
+    }
+
+    /// The musical position at `seconds`, rounded down to a whole tick.
+    pub fn seconds_to_position(&self, seconds: Float) -> BarBeatTick {
+        let ticks = num_traits::Float::floor(self.seconds_to_ticks(seconds));
+        self.ticks_to_position(if ticks > 0.0 { ticks as usize } else { 0 })
+    }
+
+    fn ticks_to_micros(&self, ticks: Float) -> Float {
+        let t = self.tempos[..self.tempos_len]
+            .iter()
+            .rev()
+            .find(|t| t.tick as Float <= ticks)
+            .unwrap_or(&self.tempos[0]);
+        t.micros + t.micros_after(ticks - t.tick as Float)
+    }
+
+    fn micros_to_ticks(&self, micros: Float) -> Float {
+        let t = self.tempos[..self.tempos_len]
+            .iter()
+            .rev()
+            .find(|t| t.micros <= micros)
+            .unwrap_or(&self.tempos[0]);
+        t.tick as Float + t.ticks_after(micros - t.micros)
+    }
+
+    fn tempo_at(&self, tick: usize) -> &Tempo {
+        self.tempos[..self.tempos_len]
+            .iter()
+            .rev()
+            .find(|t| t.tick <= tick)
+            .unwrap_or(&self.tempos[0])
+    }
+
+    fn meter_at_bar(&self, bar: usize) -> &Meter {
+        self.meters[..self.meters_len]
+            .iter()
+            .rev()
+            .find(|m| m.bar <= bar)
+            .unwrap_or(&self.meters[0])
+    }
+
+    //sort the changes and recompute the tick and time at which they happen
+    fn update(&mut self) {
+        let meters = &mut self.meters[..self.meters_len];
+        meters.sort_unstable_by_key(|m| m.bar);
+        let clock = self.tempos[0].clock;
+        for i in 1..meters.len() {
+            let prev = meters[i - 1];
+            meters[i].tick =
+                prev.tick + (meters[i].bar - prev.bar) * prev.sig.ticks_per_bar(&clock);
+        }
+
+        for i in 0..self.tempos_len {
+            let t = self.tempos[i];
+            let tick = self.position_to_ticks(t.position);
+            self.tempos[i].tick = tick;
+            self.tempos[i].ramp_ticks = match t.ramp {
+                Some((_, length)) => {
+                    let end = BarBeatTick::new(
+                        t.position.bar + length.bar,
+                        t.position.beat + length.beat,
+                        t.position.tick + length.tick,
+                    );
+                    self.position_to_ticks(end) - tick
+                }
+                None => 0,
+            };
+        }
+        let tempos = &mut self.tempos[..self.tempos_len];
+        //the tempo at 0:0:0 always sorts first
+        tempos.sort_unstable_by_key(|t| (t.tick, t.position));
+        tempos[0].from_bpm = tempos[0].clock.bpm;
+        for i in 1..tempos.len() {
+            let prev = tempos[i - 1];
+            let ticks = (tempos[i].tick - prev.tick) as Float;
+            tempos[i].micros = prev.micros + prev.micros_after(ticks);
+            tempos[i].from_bpm = prev.bpm(ticks);
+        }
+    }
+}
+
+impl Tempo {
+    //the ramp, if there is one and it has a length
+    fn ramp(&self) -> Option<(TempoRamp, Float)> {
+        match self.ramp {
+            Some((ramp, _)) if self.ramp_ticks > 0 && self.from_bpm != self.clock.bpm => {
+                Some((ramp, self.ramp_ticks as Float))
+            }
+            _ => None,
+        }
+    }
+
+    //the bpm `ticks` after the start of this tempo
+    fn bpm(&self, ticks: Float) -> Float {
+        let (from, to) = (self.from_bpm, self.clock.bpm);
+        match self.ramp() {
+            Some((ramp, length)) if ticks < length => {
+                let x = ticks / length;
+                match ramp {
+                    TempoRamp::Linear => from + (to - from) * x,
+                    TempoRamp::Exponential => {
+                        from * num_traits::Float::exp(num_traits::Float::ln(to / from) * x)
+                    }
+                }
+            }
+            _ => to,
+        }
+    }
+
+    //the micro seconds from the start of this tempo to `ticks` after it, integrating the period
+    //over the ramp
+    fn micros_after(&self, ticks: Float) -> Float {
+        let (from, to) = (self.from_bpm, self.clock.bpm);
+        let period = self.clock.period_micros;
+        match self.ramp() {
+            Some((ramp, length)) => {
+                let x = if ticks < length { ticks } else { length };
+                //micros per tick is k / bpm
+                let k = period * to;
+                let ramp_micros = match ramp {
+                    TempoRamp::Linear => {
+                        let d = (to - from) / length;
+                        k / d * num_traits::Float::ln_1p(d * x / from)
+                    }
+                    TempoRamp::Exponential => {
+                        let r = num_traits::Float::ln(to / from) / length;
+                        -k / (from * r) * num_traits::Float::exp_m1(-r * x)
+                    }
+                };
+                ramp_micros + (ticks - x) * period
+            }
+            None => ticks * period,
+        }
+    }
+
+    //the inverse of `micros_after`
+    fn ticks_after(&self, micros: Float) -> Float {
+        let (from, to) = (self.from_bpm, self.clock.bpm);
+        let period = self.clock.period_micros;
+        match self.ramp() {
+            Some((ramp, length)) => {
+                let ramp_micros = self.micros_after(length);
+                if micros >= ramp_micros {
+                    return length + (micros - ramp_micros) / period;
+                }
+                let k = period * to;
+                match ramp {
+                    TempoRamp::Linear => {
+                        let d = (to - from) / length;
+                        from / d * num_traits::Float::exp_m1(micros * d / k)
+                    }
+                    TempoRamp::Exponential => {
+                        let r = num_traits::Float::ln(to / from) / length;
+                        -num_traits::Float::ln_1p(-micros * from * r / k) / r
+                    }
+                }
+            }
+            None => micros / period,
+        }
+    }
+}
+
+impl<const N: usize> ClockPeriodGet for TempoMap<N> {
+    /// The time between `tick` and the next, the integral of the period over a ramp.
+    fn period_micros_at(&self, tick: usize) -> Float {
+        let t = self.tempo_at(tick);
+        let ticks = (tick - t.tick) as Float;
+        t.micros_after(ticks + 1.0) - t.micros_after(ticks)
+    }
+
+    fn tick_to_micros(&self, tick: usize) -> Float {
+        self.ticks_to_micros(tick as Float)
+    }
+
+    fn micros_to_tick(&self, micros: Float) -> Float {
+        self.micros_to_ticks(micros)
+    }
+}
+
+/// A `RootClock` doesn't wait while the map is being edited, it keeps the last period it read,
+/// only resyncing to the transport waits for the lock.
+impl<const N: usize> ClockPeriodGet for &'static SpinMutex<TempoMap<N>> {
+    fn period_micros_at(&self, tick: usize) -> Float {
+        self.lock().period_micros_at(tick)
+    }
+
+    fn try_period_micros_at(&self, tick: usize) -> Option<Float> {
+        self.try_lock().map(|map| map.period_micros_at(tick))
+    }
+
+    fn tick_to_micros(&self, tick: usize) -> Float {
+        self.lock().tick_to_micros(tick)
+    }
+
+    fn micros_to_tick(&self, micros: Float) -> Float {
+        self.lock().micros_to_tick(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let mut map: TempoMap<4> =
+            TempoMap::new(ClockData::new(120.0, 96), TimeSignature::default());
+        assert_eq!(
+            384 * 2 + 96 + 3,
+            map.position_to_ticks(BarBeatTick::new(2, 1, 3))
+        );
+
+        //3/4 from bar 2, 6/8 from bar 4
+        assert!(map.set_signature(4, TimeSignature::new(6, 8)).is_ok());
+        assert!(map.set_signature(2, TimeSignature::new(3, 4)).is_ok());
+        assert_eq!(TimeSignature::new(3, 4), map.signature_at(3));
+        assert_eq!(768, map.position_to_ticks(BarBeatTick::new(2, 0, 0)));
+        assert_eq!(
+            768 + 288 * 2,
+            map.position_to_ticks(BarBeatTick::new(4, 0, 0))
+        );
+        assert_eq!(
+            1344 + 288 + 48 * 5 + 7,
+            map.position_to_ticks(BarBeatTick::new(5, 5, 7))
+        );
+        for p in &[
+            BarBeatTick::new(0, 3, 95),
+            BarBeatTick::new(2, 2, 0),
+            BarBeatTick::new(4, 0, 0),
+            BarBeatTick::new(5, 5, 7),
+        ] {
+            assert_eq!(*p, map.ticks_to_position(map.position_to_ticks(*p)));
+        }
+
+        assert!(map.set_signature(1, TimeSignature::new(5, 4)).is_ok());
+        assert_eq!(
+            Err(TimeSignature::new(7, 8)),
+            map.set_signature(6, TimeSignature::new(7, 8))
+        );
+        assert!(!map.remove_signature(0));
+        assert!(map.remove_signature(1));
+        assert_eq!(768, map.position_to_ticks(BarBeatTick::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn seconds() {
+        let mut map: TempoMap<4> =
+            TempoMap::new(ClockData::new(120.0, 96), TimeSignature::default());
+        //2 bars at 120, 1 at 60, then 240
+        assert!(map.set_tempo(BarBeatTick::new(2, 0, 0), 60.0).is_ok());
+        assert!(map.set_tempo(BarBeatTick::new(3, 0, 0), 240.0).is_ok());
+        assert_eq!(60.0, map.bpm_at(768));
+        assert_eq!(120.0, map.bpm_at(767));
+
+        let close = |a: Float, b: Float| (a - b).abs() < 1e-6;
+        assert!(close(
+            4.0,
+            map.position_to_seconds(BarBeatTick::new(2, 0, 0))
+        ));
+        assert!(close(
+            8.0,
+            map.position_to_seconds(BarBeatTick::new(3, 0, 0))
+        ));
+        assert!(close(
+            9.0,
+            map.position_to_seconds(BarBeatTick::new(4, 0, 0))
+        ));
+        assert!(close(
+            8.125,
+            map.position_to_seconds(BarBeatTick::new(3, 0, 48))
+        ));
+        assert_eq!(BarBeatTick::new(2, 1, 48), map.seconds_to_position(5.5));
+        assert!(close(1152.0 + 48.0, map.seconds_to_ticks(8.125)));
+        assert!(close(8.125, map.ticks_to_seconds(1200.0)));
+
+        //a time signature change keeps the tempo changes at their musical positions
+        assert!(map.set_signature(1, TimeSignature::new(3, 4)).is_ok());
+        assert!(close(
+            3.5,
+            map.position_to_seconds(BarBeatTick::new(2, 0, 0))
+        ));
+        assert_eq!(60.0, map.bpm_at(672));
+
+        assert!(map.remove_tempo(BarBeatTick::new(2, 0, 0)));
+        assert!(!map.remove_tempo(BarBeatTick::new(0, 0, 0)));
+        assert!(close(
+            5.0,
+            map.position_to_seconds(BarBeatTick::new(3, 0, 0))
+        ));
+    }
+
+    #[test]
+    fn ramps() {
+        let close = |a: Float, b: Float| (a - b).abs() < 1e-3;
+        for (ramp, micros, bpm) in &[
+            //2e6 * ln(2) and 1e6 / ln(2)
+            (TempoRamp::Linear, 1_386_294.361, 90.0),
+            (TempoRamp::Exponential, 1_442_695.041, 84.852_814),
+        ] {
+            //60 bpm, 10000us per tick, ramp to 120 over 2 beats from the second beat
+            let mut map: TempoMap<2> =
+                TempoMap::new(ClockData::new(60.0, 100), TimeSignature::default());
+            assert!(map
+                .set_tempo_ramp(
+                    BarBeatTick::new(0, 1, 0),
+                    120.0,
+                    BarBeatTick::new(0, 2, 0),
+                    *ramp
+                )
+                .is_ok());
+            assert_eq!(60.0, map.bpm_at(100));
+            assert!(close(*bpm, map.bpm_at(200)));
+            assert_eq!(120.0, map.bpm_at(300));
+
+            assert!(close(1_000_000.0 + micros, map.tick_to_micros(300)));
+            assert!(close(
+                1_000_000.0 + micros + 50_000.0,
+                map.tick_to_micros(310)
+            ));
+            for tick in &[50, 100, 150, 299, 300, 400] {
+                let m = map.tick_to_micros(*tick);
+                assert!(close(*tick as Float, map.micros_to_tick(m)));
+            }
+
+            //the periods of the ticks add up to the time of the end of the ramp
+            let sum: Float = (0..300).map(|t| map.period_micros_at(t)).sum();
+            assert!(close(map.tick_to_micros(300), sum));
+            assert!(close(5_000.0, map.period_micros_at(300)));
+        }
+
+        //a ramp at the start is a plain change, a later change cuts a ramp short
+        let mut map: TempoMap<3> =
+            TempoMap::new(ClockData::new(120.0, 100), TimeSignature::default());
+        assert!(map
+            .set_tempo_ramp(
+                BarBeatTick::new(0, 0, 0),
+                60.0,
+                BarBeatTick::new(1, 0, 0),
+                TempoRamp::Linear
+            )
+            .is_ok());
+        assert_eq!(60.0, map.bpm_at(200));
+        assert!(map.set_tempo(BarBeatTick::new(0, 0, 0), 120.0).is_ok());
+        assert!(map
+            .set_tempo_ramp(
+                BarBeatTick::new(1, 0, 0),
+                60.0,
+                BarBeatTick::new(1, 0, 0),
+                TempoRamp::Linear
+            )
+            .is_ok());
+        assert!(map.set_tempo(BarBeatTick::new(1, 2, 0), 100.0).is_ok());
+        assert!(close(90.15, map.bpm_at(599)));
+        assert_eq!(100.0, map.bpm_at(600));
+        //4e6 * ln(4/3)
+        assert!(close(
+            1_150_728.289,
+            map.tick_to_micros(600) - map.tick_to_micros(400)
+        ));
+    }
+
+    #[test]
+    fn clock_period() {
+        let mut map: TempoMap<2> =
+            TempoMap::new(ClockData::new(120.0, 100), TimeSignature::default());
+        assert!(map.set_tempo(BarBeatTick::new(0, 2, 0), 60.0).is_ok());
+        assert_eq!(Err(90.0), map.set_tempo(BarBeatTick::new(1, 0, 0), 90.0));
+
+        assert_eq!(5000.0, map.period_micros_at(199));
+        assert_eq!(10000.0, map.period_micros_at(200));
+        assert_eq!(1_000_000.0, map.tick_to_micros(200));
+        assert_eq!(1_100_000.0, map.tick_to_micros(210));
+        assert_eq!(205.5, map.micros_to_tick(1_055_000.0));
+    }
+
+    #[test]
+    fn clock_context_ticks_follow_map() {
+        use crate::{
+            context::tests::TestContext,
+            event::EventEvalContext,
+            graph::{
+                func::LeafFunc,
+                root::{clock::RootClock, GraphRootExec},
+                GraphLeafWrapper,
+            },
+            tick::{TickResched, TickSched},
+        };
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static LAST: AtomicUsize = AtomicUsize::new(0);
+
+        //context absolute times at the clock's tick are now, before and after the change
+        let leaf = GraphLeafWrapper::new(LeafFunc::new(|context: &mut dyn EventEvalContext<()>| {
+            let tick = TickSched::ContextAbsolute(context.context_tick_now());
+            assert_eq!(
+                context.tick_now(),
+                tick.to_absolute(context.as_tick_context())
+            );
+            LAST.store(context.context_tick_now(), Ordering::SeqCst);
+        }));
+
+        //60 bpm, then 30 from bar 1
+        let mut map: TempoMap<2> =
+            TempoMap::new(ClockData::new(60.0, 24), TimeSignature::default());
+        assert!(map.set_tempo(BarBeatTick::new(1, 0, 0), 30.0).is_ok());
+        let mut clock = RootClock::new(map, true, false);
+        let mut children = (leaf,);
+        let mut context = TestContext::new(0, 1000);
+        let mut tick = 0;
+        while tick < 8000 {
+            match clock.event_eval(&mut context, &mut children) {
+                TickResched::ContextRelative(d) => tick += d,
+                r => panic!("unexpected {:?}", r),
+            }
+            context.set_tick(tick);
+        }
+        assert!(LAST.load(Ordering::SeqCst) > 96 + 24);
+    }
+
+    #[test]
+    fn shared_clock_period() {
+        use crate::{
+            context::tests::TestContext,
+            graph::root::{clock::RootClock, GraphRootExec},
+            tick::TickResched,
+        };
+
+        lazy_static::lazy_static! {
+            static ref MAP: SpinMutex<TempoMap<2>> = SpinMutex::new(TempoMap::new(
+                ClockData::new(120.0, 100),
+                TimeSignature::default()
+            ));
+        }
+        let map: &'static SpinMutex<TempoMap<2>> = &MAP;
+        assert_eq!(Some(5000.0), map.try_period_micros_at(0));
+
+        //1000 ticks per second, 5 ticks per clock tick
+        let mut clock: RootClock<_, bool, bool, ()> = RootClock::new(map, true, false);
+        let mut context = TestContext::new(0, 1000);
+        assert_eq!(
+            TickResched::ContextRelative(5),
+            clock.event_eval(&mut context, &mut ())
+        );
+        {
+            //the clock keeps the last period while the map is being edited
+            let mut edit = MAP.lock();
+            assert!(edit.set_tempo(BarBeatTick::new(0, 0, 0), 60.0).is_ok());
+            assert_eq!(None, map.try_period_micros_at(1));
+            context.set_tick(5);
+            assert_eq!(
+                TickResched::ContextRelative(5),
+                clock.event_eval(&mut context, &mut ())
+            );
+        }
+        context.set_tick(10);
+        assert_eq!(
+            TickResched::ContextRelative(10),
+            clock.event_eval(&mut context, &mut ())
+        );
+    }
+}
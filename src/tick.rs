@@ -1,7 +1,13 @@
 //! Times represented as ticks
+//!
+//! Absolute ticks are allowed to roll over, they are compared with serial number arithmetic, see
+//! `tick_cmp`.
 use crate::Float;
+use core::{
+    any::{Any, TypeId},
+    cmp::Ordering,
+};
 
-//XXX maybe context ticks should have an isize absolute offset?
 /// A representation of time, absolute, relative, context absolute or relative.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
@@ -13,11 +19,12 @@ pub enum TickSched {
 }
 
 /// A forward only relative time unit.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "with_serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TickResched {
     Relative(usize),
     ContextRelative(usize), /*ContextRelative(usize, isize) */
+    #[default]
     None,
 }
 
@@ -31,6 +38,12 @@ pub trait TickContext {
         1.0e6 / (self.ticks_per_second() as Float)
     }
 
+    /// The fraction of a tick, in [0, 1), after `tick_now` that this context's exact position is
+    /// at, when its ticks don't line up with whole ticks
+    fn tick_sub(&self) -> Float {
+        0.0
+    }
+
     /// Context
     fn context_tick_now(&self) -> usize {
         self.tick_now()
@@ -45,76 +58,201 @@ pub trait TickContext {
         1.0e6 / (self.context_ticks_per_second() as Float)
     }
 
-    /// Which absolute tick does context 0 happen
+    /// The offset from `tick_now` to the tick at which context tick 0 happened
+    ///
+    /// Defaults to extrapolating back from `context_tick_now` with `context_to_ticks`, contexts
+    /// whose ratio changes over time track it instead.
     fn context_tick_offset(&self) -> isize {
-        0isize
+        0isize.wrapping_sub(context_to_ticks(to_offset(self.context_tick_now()), self))
     }
 
     /// context ticks, base ticks
     fn context_tick_ratio(&self) -> (usize, usize) {
         (self.context_ticks_per_second(), self.ticks_per_second())
     }
+
+    /// Transport
+    /// The transport position, in absolute ticks, of `tick_now`
+    fn transport_tick(&self) -> usize {
+        self.tick_now()
+    }
+
+    /// Changes every time the transport is located, roots resync when it does
+    fn transport_epoch(&self) -> usize {
+        0
+    }
+
+    /// The first tick after the current run, the transport can only be located there, roots
+    /// that follow the transport wake up at it so they resync on time
+    fn transport_sync_tick(&self) -> Option<usize> {
+        None
+    }
+
+    /// Scoped variables
+    /// The innermost value of type `type_id` pushed for `key` by a parent, see `ContextKey`
+    fn context_var(&self, _key: &str, _type_id: TypeId) -> Option<&dyn Any> {
+        None
+    }
 }
 
+/// Offset a tick, wrapping around the boundaries of `usize`.
 pub fn offset_tick(tick: usize, offset: isize) -> usize {
-    if offset >= 0isize {
-        tick.saturating_add(offset as usize)
+    tick.wrapping_add(offset as usize)
+}
+
+/// The signed distance from `b` to `a`, accounting for rollover.
+pub fn tick_diff(a: usize, b: usize) -> isize {
+    a.wrapping_sub(b) as isize
+}
+
+/// Compare two ticks, accounting for rollover.
+///
+/// Uses serial number arithmetic, `a` is less than `b` if `b` comes less than half of the tick
+/// range after `a`. Ticks that are further apart than that do not compare correctly.
+pub fn tick_cmp(a: usize, b: usize) -> Ordering {
+    tick_diff(a, b).cmp(&0)
+}
+
+/// Is `a` before `b`, accounting for rollover.
+pub fn tick_lt(a: usize, b: usize) -> bool {
+    tick_diff(a, b) < 0
+}
+
+//saturate instead of wrapping to a negative offset
+pub(crate) fn to_offset(ticks: usize) -> isize {
+    core::cmp::min(ticks, isize::MAX as usize) as isize
+}
+
+/// Convert context ticks to fractional ticks, with the ratio of the context tick period to the
+/// tick period.
+pub fn context_to_ticks_exact<C>(ticks: Float, context: &C) -> Float
+where
+    C: TickContext + ?Sized,
+{
+    let period = context.tick_period_micros();
+    let context_period = context.context_tick_period_micros();
+    //no context ticks, or no ticks
+    if period > 0.0 && num_traits::Float::is_finite(context_period) {
+        ticks * context_period / period
+    } else {
+        0.0
+    }
+}
+
+/// Convert a number of context ticks to ticks, see `context_to_ticks_exact`.
+///
+/// Rounds to the nearest tick and saturates at the bounds of `isize`, context ticks that are
+/// the same length as ticks are returned unchanged.
+pub fn context_to_ticks<C>(offset: isize, context: &C) -> isize
+where
+    C: TickContext + ?Sized,
+{
+    if is_identity(context) {
+        offset
     } else {
-        tick.saturating_sub(-offset as usize)
+        //float to int casts saturate
+        num_traits::Float::round(context_to_ticks_exact(offset as Float, context)) as isize
     }
 }
 
+//context ticks are ticks, convert without going through Float
+fn is_identity<C>(context: &C) -> bool
+where
+    C: TickContext + ?Sized,
+{
+    context.context_tick_period_micros() == context.tick_period_micros()
+}
+
+/// The offset from `tick_now` of a context relative time.
+///
+/// Context relative times are relative to the exact position, `tick_now` plus `tick_sub`, the
+/// sum is rounded to the nearest tick, once.
+pub fn context_relative_to_offset<C>(offset: isize, context: &C) -> isize
+where
+    C: TickContext + ?Sized,
+{
+    num_traits::Float::round(context.tick_sub() + context_to_ticks_exact(offset as Float, context))
+        as isize
+}
+
+/// The offset from `tick_now` of a context absolute time, relative to `context_tick_offset`.
+///
+/// Context ticks past `isize::MAX` saturate, unless context ticks are ticks, then they wrap like
+/// absolute ticks.
+pub fn context_absolute_to_offset<C>(tick: usize, context: &C) -> isize
+where
+    C: TickContext + ?Sized,
+{
+    let offset = if is_identity(context) {
+        tick as isize
+    } else {
+        context_to_ticks(to_offset(tick), context)
+    };
+    context.context_tick_offset().wrapping_add(offset)
+}
+
 impl TickSched {
-    pub fn add<'a>(&self, d: TickResched, context: &'a dyn TickContext) -> Self {
-        //XXX update with context math
-        let (cratio, bratio) = context.context_tick_ratio();
+    /// Add a duration.
+    ///
+    /// Durations of the same kind as the time are added directly. When the kinds differ, context
+    /// ticks are converted to ticks with `context_to_ticks`:
+    /// * `ContextAbsolute + Relative` is absolute, see `to_absolute`.
+    /// * `Absolute + ContextRelative` is absolute.
+    /// * `Relative + ContextRelative` and `ContextRelative + Relative` are relative.
+    ///
+    /// Offsets saturate instead of overflowing, absolute ticks wrap.
+    pub fn add(&self, d: TickResched, context: &dyn TickContext) -> Self {
         match d {
-            TickResched::Relative(offset) => match *self {
-                TickSched::Absolute(tick) => {
-                    TickSched::Absolute(offset_tick(tick, offset as isize))
-                }
-                TickSched::ContextAbsolute(_ctick) => unimplemented!(),
-                TickSched::Relative(aoffset) => TickSched::Relative(offset as isize + aoffset),
-                TickSched::ContextRelative(_coffset) => unimplemented!(),
-            },
-            TickResched::ContextRelative(offset) => match *self {
-                TickSched::Absolute(tick) => {
-                    let tick = offset_tick(
-                        tick,
-                        context.context_tick_offset().saturating_add(
-                            //XXX conversion to isize could overflow.. figure this out
-                            offset.saturating_mul(bratio) as isize / (cratio as isize),
-                        ),
-                    );
-                    TickSched::Absolute(tick)
+            TickResched::Relative(offset) => {
+                let offset = to_offset(offset);
+                match *self {
+                    TickSched::Absolute(tick) => TickSched::Absolute(offset_tick(tick, offset)),
+                    TickSched::ContextAbsolute(_) => {
+                        TickSched::Absolute(offset_tick(self.to_absolute(context), offset))
+                    }
+                    TickSched::Relative(aoffset) => {
+                        TickSched::Relative(aoffset.saturating_add(offset))
+                    }
+                    TickSched::ContextRelative(coffset) => TickSched::Relative(
+                        context_relative_to_offset(coffset, context).saturating_add(offset),
+                    ),
                 }
-                TickSched::ContextAbsolute(tick) => {
-                    TickSched::ContextAbsolute(offset_tick(tick, offset as isize))
-                }
-                TickSched::Relative(_offset) => unimplemented!(),
-                TickSched::ContextRelative(coffset) => {
-                    TickSched::ContextRelative(offset_tick(offset, coffset) as isize)
+            }
+            TickResched::ContextRelative(offset) => {
+                let offset = to_offset(offset);
+                match *self {
+                    TickSched::Absolute(tick) => {
+                        TickSched::Absolute(offset_tick(tick, context_to_ticks(offset, context)))
+                    }
+                    TickSched::ContextAbsolute(tick) => {
+                        TickSched::ContextAbsolute(offset_tick(tick, offset))
+                    }
+                    TickSched::Relative(aoffset) => TickSched::Relative(
+                        aoffset.saturating_add(context_to_ticks(offset, context)),
+                    ),
+                    TickSched::ContextRelative(coffset) => {
+                        TickSched::ContextRelative(coffset.saturating_add(offset))
+                    }
                 }
-            },
+            }
             TickResched::None => *self,
         }
     }
 
-    pub fn to_absolute<'a>(&self, context: &'a dyn TickContext) -> usize {
-        let (cratio, bratio) = context.context_tick_ratio();
+    /// The absolute tick of this time, relative times are relative to `tick_now` and context
+    /// absolute times to the tick at which context tick 0 happened, see `context_tick_offset`.
+    pub fn to_absolute(&self, context: &dyn TickContext) -> usize {
         match *self {
             TickSched::Absolute(tick) => tick,
             TickSched::Relative(offset) => offset_tick(context.tick_now(), offset),
-            TickSched::ContextAbsolute(tick) => {
-                offset_tick(context.tick_now(), context.context_tick_offset())
-                    .saturating_add(tick.saturating_mul(bratio) / cratio)
-            }
-            TickSched::ContextRelative(_offset) => {
-                unimplemented!();
-                //convert relative to absolute
-                //let offset = offset.saturating_mul(ratio.0 as isize) / (div as isize);
-                //offset_tick(now, offset).saturating_add(context_tick_offset)
-            }
+            TickSched::ContextAbsolute(tick) => offset_tick(
+                context.tick_now(),
+                context_absolute_to_offset(tick, context),
+            ),
+            TickSched::ContextRelative(offset) => offset_tick(
+                context.tick_now(),
+                context_relative_to_offset(offset, context),
+            ),
         }
     }
 }
@@ -125,12 +263,6 @@ impl Default for TickSched {
     }
 }
 
-impl Default for TickResched {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,82 +373,208 @@ mod tests {
             tick.add(TickResched::ContextRelative(2), &context)
         );
 
-        //context starts before absolute
+        //a duration doesn't depend on where context tick 0 is
         context.context_tick_offset = -20;
-        tick = TickSched::Absolute(0);
-        assert_eq!(
-            TickSched::Absolute(0),
-            tick.add(TickResched::ContextRelative(0), &context)
-        );
+        tick = TickSched::Absolute(usize::MAX - 9);
+        assert_eq!(tick, tick.add(TickResched::ContextRelative(0), &context));
         assert_eq!(
             TickSched::Absolute(0),
             tick.add(TickResched::ContextRelative(1), &context)
         );
-        assert_eq!(
-            TickSched::Absolute(0),
-            tick.add(TickResched::ContextRelative(2), &context)
-        );
         assert_eq!(
             TickSched::Absolute(10),
-            tick.add(TickResched::ContextRelative(3), &context)
+            tick.add(TickResched::ContextRelative(2), &context)
         );
 
-        //context 2x slower than base
+        //2 context ticks per tick, rounds to nearest
         context.context_ticks_per_second = 44100 * 2;
         context.context_tick_offset = 0;
         tick = TickSched::Absolute(0);
         assert_eq!(tick, tick.add(TickResched::ContextRelative(0), &context));
         assert_eq!(
-            TickSched::Absolute(0),
+            TickSched::Absolute(1),
             tick.add(TickResched::ContextRelative(1), &context)
         );
         assert_eq!(
             TickSched::Absolute(1),
             tick.add(TickResched::ContextRelative(2), &context)
         );
+        assert_eq!(
+            TickSched::Absolute(2),
+            tick.add(TickResched::ContextRelative(3), &context)
+        );
 
         context.context_tick_offset = 1;
+        tick = TickSched::Absolute(100);
+        assert_eq!(tick, tick.add(TickResched::ContextRelative(0), &context));
         assert_eq!(
-            TickSched::Absolute(1),
-            tick.add(TickResched::ContextRelative(0), &context)
+            TickSched::Absolute(101),
+            tick.add(TickResched::ContextRelative(2), &context)
+        );
+    }
+
+    #[test]
+    fn assert_add_mixed() {
+        let mut context = TestContext::new();
+        //10 ticks per context tick
+        context.context_ticks_per_second = 4410;
+        context.context_tick_offset = 3;
+
+        assert_eq!(
+            TickSched::Relative(25),
+            TickSched::Relative(5).add(TickResched::ContextRelative(2), &context)
         );
         assert_eq!(
-            TickSched::Absolute(1),
-            tick.add(TickResched::ContextRelative(1), &context)
+            TickSched::Relative(-15),
+            TickSched::Relative(-35).add(TickResched::ContextRelative(2), &context)
         );
         assert_eq!(
-            TickSched::Absolute(2),
-            tick.add(TickResched::ContextRelative(2), &context)
+            TickSched::Relative(27),
+            TickSched::ContextRelative(2).add(TickResched::Relative(7), &context)
+        );
+        assert_eq!(
+            TickSched::Relative(-13),
+            TickSched::ContextRelative(-2).add(TickResched::Relative(7), &context)
+        );
+        assert_eq!(
+            TickSched::ContextRelative(5),
+            TickSched::ContextRelative(-2).add(TickResched::ContextRelative(7), &context)
         );
 
-        tick = TickSched::Absolute(100);
+        //converted to absolute
+        context.tick_now = 100;
         assert_eq!(
-            TickSched::Absolute(101),
-            tick.add(TickResched::ContextRelative(0), &context)
+            TickSched::Absolute(100 + 3 + 40 + 7),
+            TickSched::ContextAbsolute(4).add(TickResched::Relative(7), &context)
         );
         assert_eq!(
-            TickSched::Absolute(101),
-            tick.add(TickResched::ContextRelative(1), &context)
+            TickSched::ContextAbsolute(11),
+            TickSched::ContextAbsolute(4).add(TickResched::ContextRelative(7), &context)
         );
+
+        //rounds to nearest
+        context.context_ticks_per_second = 44100 * 4;
+        context.context_tick_offset = 0;
         assert_eq!(
-            TickSched::Absolute(102),
-            tick.add(TickResched::ContextRelative(2), &context)
+            TickSched::Relative(2),
+            TickSched::Relative(0).add(TickResched::ContextRelative(7), &context)
+        );
+        assert_eq!(
+            TickSched::Relative(1),
+            TickSched::Relative(0).add(TickResched::ContextRelative(5), &context)
+        );
+        assert_eq!(
+            TickSched::Relative(-2),
+            TickSched::ContextRelative(-7).add(TickResched::Relative(0), &context)
+        );
+    }
+
+    #[test]
+    fn assert_add_overflow() {
+        let mut context = TestContext::new();
+        //100 ticks per context tick
+        context.context_ticks_per_second = 441;
+
+        assert_eq!(
+            TickSched::Relative(isize::MAX),
+            TickSched::Relative(1).add(TickResched::Relative(usize::MAX), &context)
+        );
+        assert_eq!(
+            TickSched::Relative(isize::MAX),
+            TickSched::Relative(1).add(TickResched::ContextRelative(usize::MAX / 4), &context)
+        );
+        assert_eq!(
+            TickSched::Relative(isize::MIN),
+            TickSched::ContextRelative(isize::MIN / 4).add(TickResched::Relative(0), &context)
+        );
+        assert_eq!(
+            TickSched::ContextRelative(isize::MAX),
+            TickSched::ContextRelative(2).add(TickResched::ContextRelative(usize::MAX), &context)
+        );
+        assert_eq!(
+            TickSched::ContextAbsolute(1),
+            TickSched::ContextAbsolute(usize::MAX - 1)
+                .add(TickResched::ContextRelative(3), &context)
+        );
+        assert_eq!(
+            TickSched::Absolute(4),
+            TickSched::Absolute(usize::MAX - 5).add(TickResched::Relative(10), &context)
+        );
+        assert_eq!(
+            TickSched::Absolute(100 - 6),
+            TickSched::Absolute(usize::MAX - 5).add(TickResched::ContextRelative(1), &context)
+        );
+
+        //no context ticks
+        context.context_ticks_per_second = 0;
+        assert_eq!(
+            TickSched::Relative(0),
+            TickSched::Relative(0).add(TickResched::ContextRelative(10), &context)
         );
     }
 
     #[test]
     fn to_absolute() {
-        //TODO
+        let mut context = TestContext::new();
+        context.tick_now = 1000;
+
+        assert_eq!(20, TickSched::Absolute(20).to_absolute(&context));
+        assert_eq!(1020, TickSched::Relative(20).to_absolute(&context));
+        assert_eq!(980, TickSched::Relative(-20).to_absolute(&context));
+        assert_eq!(1020, TickSched::ContextRelative(20).to_absolute(&context));
+        assert_eq!(1020, TickSched::ContextAbsolute(20).to_absolute(&context));
+
+        //10 ticks per context tick
+        context.context_ticks_per_second = 4410;
+        assert_eq!(1200, TickSched::ContextRelative(20).to_absolute(&context));
+        assert_eq!(800, TickSched::ContextRelative(-20).to_absolute(&context));
+        assert_eq!(1200, TickSched::ContextAbsolute(20).to_absolute(&context));
+        context.context_tick_offset = -5;
+        assert_eq!(1195, TickSched::ContextAbsolute(20).to_absolute(&context));
+
+        //wraps
+        context.tick_now = 5;
+        assert_eq!(
+            usize::MAX - 4,
+            TickSched::Relative(-10).to_absolute(&context)
+        );
+        assert_eq!(
+            usize::MAX - 94,
+            TickSched::ContextRelative(-10).to_absolute(&context)
+        );
+        context.tick_now = usize::MAX;
+        assert_eq!(99, TickSched::ContextRelative(10).to_absolute(&context));
+    }
+
+    #[test]
+    fn assert_tick_cmp() {
+        assert_eq!(Ordering::Equal, tick_cmp(0, 0));
+        assert_eq!(Ordering::Less, tick_cmp(0, 1));
+        assert_eq!(Ordering::Greater, tick_cmp(1, 0));
+        assert_eq!(Ordering::Less, tick_cmp(usize::MAX, 0));
+        assert_eq!(Ordering::Greater, tick_cmp(0, usize::MAX));
+        assert_eq!(Ordering::Less, tick_cmp(usize::MAX - 100, 100));
+        assert_eq!(Ordering::Greater, tick_cmp(100, usize::MAX - 100));
+
+        assert!(tick_lt(usize::MAX - 5, 3));
+        assert!(!tick_lt(3, usize::MAX - 5));
+        assert!(!tick_lt(3, 3));
+
+        assert_eq!(9, tick_diff(3, usize::MAX - 5));
+        assert_eq!(-9, tick_diff(usize::MAX - 5, 3));
+        assert_eq!(0, tick_diff(usize::MAX, usize::MAX));
     }
 
     #[test]
     fn assert_offset_tick() {
-        assert_eq!(0usize, offset_tick(0, -2));
+        assert_eq!(usize::MAX - 1, offset_tick(0, -2));
         assert_eq!(0usize, offset_tick(0, 0));
         assert_eq!(0usize, offset_tick(1, -1));
-        assert_eq!(0usize, offset_tick(1, -2));
+        assert_eq!(usize::MAX, offset_tick(1, -2));
         assert_eq!(0usize, offset_tick(123, -123));
-        assert_eq!(0usize, offset_tick(123, -12000));
+        assert_eq!(usize::MAX - 11876, offset_tick(123, -12000));
+        assert_eq!(0usize, offset_tick(usize::MAX, 1));
+        assert_eq!(9usize, offset_tick(usize::MAX - 10, 20));
         assert_eq!(2usize, offset_tick(2, 0));
         assert_eq!(2usize, offset_tick(0, 2));
         assert_eq!(2usize, offset_tick(1, 1));
@@ -0,0 +1,88 @@
+//! Transport control, play/stop, locate and looping.
+use core::ops::Range;
+
+/// The transport of a `SchedExec`.
+///
+/// The transport position is the scheduler's tick minus an offset, so locating only changes the
+/// offset and pending events keep their ticks. Every locate changes the `epoch`, roots compare it
+/// with the last value they saw, via `TickContext::transport_epoch`, to know when to resync to
+/// `TickContext::transport_tick`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transport {
+    playing: bool,
+    offset: usize,
+    epoch: usize,
+    loop_region: Option<Range<usize>>,
+}
+
+impl Transport {
+    /// Create a playing transport, positioned at tick 0, without a loop.
+    pub fn new() -> Self {
+        Self {
+            playing: true,
+            offset: 0,
+            epoch: 0,
+            loop_region: None,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// The transport position at the given scheduler `tick`.
+    pub fn position(&self, tick: usize) -> usize {
+        tick.wrapping_sub(self.offset)
+    }
+
+    /// Move the transport so that it is at `position` at the given scheduler `tick`.
+    pub fn locate(&mut self, tick: usize, position: usize) {
+        self.offset = tick.wrapping_sub(position);
+        self.epoch = self.epoch.wrapping_add(1);
+    }
+
+    /// The difference between the scheduler tick and the transport position.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Changes every time the transport is located.
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    pub fn loop_region(&self) -> Option<Range<usize>> {
+        self.loop_region.clone()
+    }
+
+    /// Set the region, in transport ticks, to loop, or `None` to stop looping.
+    ///
+    /// Empty regions are treated as `None`.
+    pub fn set_loop(&mut self, region: Option<Range<usize>>) {
+        self.loop_region = region.filter(|r| r.start < r.end);
+    }
+
+    /// The number of ticks from the scheduler `tick` until the transport reaches the end of the
+    /// loop region, if it is looping and before the end.
+    pub fn ticks_until_loop_end(&self, tick: usize) -> Option<usize> {
+        let position = self.position(tick);
+        match &self.loop_region {
+            Some(r) if position < r.end => Some(r.end - position),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}